@@ -0,0 +1,2112 @@
+use std::collections::HashMap;
+use crate::statements::{Expression, ExpressionType, Operation, Operator, Statement, StatementType};
+use crate::types::{Directive, Function, Parameter, Record, Type};
+
+const REALS: [&str; 27] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M",
+    "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "θ"
+];
+const STRINGS: [&str; 10] = ["Str1", "Str2", "Str3", "Str4", "Str5", "Str6", "Str7", "Str8", "Str9", "Str0"];
+const MATRICES: [&str; 10] = ["[A]", "[B]", "[C]", "[D]", "[E]", "[F]", "[G]", "[H]", "[I]", "[J]"];
+
+// TI-Basic operator precedence, higher values bind tighter.
+const OR: u8 = 1;
+const AND: u8 = 2;
+const RELATIONAL: u8 = 3;
+const ADDITIVE: u8 = 4;
+const MULTIPLICATIVE: u8 = 5;
+const NEGATION: u8 = 6;
+const POWER: u8 = 7;
+const POSTFIX: u8 = 8;
+const ATOM: u8 = 9;
+
+mod builtins;
+mod callables;
+mod exceptions;
+mod loops;
+mod matching;
+mod matrices;
+mod modes;
+mod records;
+mod scopes;
+#[cfg(test)]
+mod tests;
+
+/// Choices made on the command line that change the generated programs.
+#[derive(Default)]
+pub(crate) struct Options {
+    /// Keep comments as `"comment` lines, which cost space on the calculator.
+    pub(crate) comments: bool,
+    /// Check for Python errors at runtime and report them with their line number.
+    pub(crate) debug: bool,
+}
+
+pub(crate) struct Program {
+    pub(crate) name: String,
+    pub(crate) lines: Vec<String>,
+}
+
+/// A lowered TI-Basic expression along with the precedence of its outermost operator.
+#[derive(Clone)]
+struct Code {
+    text: String,
+    precedence: u8,
+}
+
+impl Code {
+
+    fn new(text: String, precedence: u8) -> Code {
+        Code { text, precedence }
+    }
+
+    fn atom(text: String) -> Code {
+        Code { text, precedence: ATOM }
+    }
+
+    fn wrap(&self, precedence: u8) -> String {
+        if self.precedence < precedence {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+/// A TI-Basic index kept as a variable part plus a constant, so bounds can be subtracted without leftover arithmetic.
+#[derive(Clone)]
+struct Bound {
+    term: Option<Code>,
+    constant: i64,
+}
+
+impl Bound {
+
+    fn code(self) -> Code {
+        match self.term {
+            Some(term) => offset(term, self.constant),
+            None => integer(self.constant),
+        }
+    }
+
+    /// The number of elements from `from` to `to`, both included.
+    fn span(from: Bound, to: Bound) -> Code {
+        let constant = to.constant - from.constant + 1;
+        match (to.term, from.term) {
+            (to, None) => Bound { term: to, constant }.code(),
+            (Some(to), Some(from)) if to.text == from.text => integer(constant),
+            (None, Some(from)) => Code::new(format!("{}-{}", constant, from.wrap(ADDITIVE + 1)), ADDITIVE),
+            (Some(to), Some(from)) => offset(Code::new(format!("{}-{}", to.wrap(ADDITIVE), from.wrap(ADDITIVE + 1)), ADDITIVE), constant),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Storage {
+    Real,
+    String,
+    List,
+    Matrix,
+    Records, // one list per field
+    Tuple,
+}
+
+struct Variable {
+    name: String,
+    type_: Type,
+}
+
+struct Generator<'a> {
+    functions: &'a [Function],
+    records: &'a [Record],
+    options: &'a Options,
+    programs: HashMap<String, String>,
+    helpers: Vec<Program>,
+    scopes: scopes::Scopes,
+    variables: HashMap<String, Variable>, // keyed by scopes::key
+    pins: HashMap<String, String>,
+    shapes: HashMap<String, Option<matrices::Shape>>,
+    lines: Vec<String>,
+    checking: bool,
+    complex: bool,
+    function: String,
+    temps: usize,
+    loops: usize,
+    labels: usize,
+    known: HashMap<String, f64>, // constants left by the statements just before the current one
+    dispatchers: Vec<callables::Dispatcher>,
+    lambdas: usize,
+    exits: Vec<loops::Exit>, // one per loop the current statement is in, innermost last
+}
+
+pub(crate) fn generate(functions: &[Function], records: &[Record], options: &Options) -> Result<Vec<Program>, String> {
+    records::check_records(records, functions)?;
+    let mut generator = Generator::new(functions, records, options)?;
+    generator.declare_globals()?;
+    for record in records {
+        generator.defaults(&record.name, &record.fields)?;
+    }
+    let mut programs = functions.iter().map(|function| generator.function(function)).collect::<Result<Vec<Program>, String>>()?;
+    programs.append(&mut generator.helpers);
+    programs.append(&mut generator.dispatch_programs()?);
+    modes::restore_real(&mut programs);
+    Ok(programs)
+}
+
+impl<'a> Generator<'a> {
+
+    fn new(functions: &'a [Function], records: &'a [Record], options: &'a Options) -> Result<Generator<'a>, String> {
+        let mut programs: HashMap<String, String> = HashMap::new();
+        for function in functions {
+            let program = program_name(&function.name)?;
+            if let Some((other, _)) = programs.iter().find(|(_, name)| **name == program) {
+                return Err(format!("functions '{}' and '{}' both compile to prgm{}", other, function.name, program));
+            }
+            programs.insert(function.name.clone(), program);
+        }
+        Ok(Generator {
+            functions,
+            records,
+            options,
+            programs,
+            helpers: Vec::new(),
+            scopes: scopes::Scopes::resolve(functions)?,
+            variables: HashMap::new(),
+            pins: HashMap::new(),
+            shapes: HashMap::new(),
+            lines: Vec::new(),
+            checking: options.debug,
+            complex: false,
+            function: String::new(),
+            temps: 0,
+            loops: 0,
+            labels: 0,
+            known: HashMap::new(),
+            dispatchers: Vec::new(),
+            lambdas: 0,
+            exits: Vec::new(),
+        })
+    }
+
+    fn function(&mut self, function: &Function) -> Result<Program, String> {
+        self.function = function.name.clone();
+        self.loops = 0;
+        self.labels = 0;
+        self.complex = function.return_type == Some(Type::Complex);
+        for directive in &function.directives {
+            match directive {
+                Directive::Var(_) => return Err(format!("'var=' applies to an assignment, not to function '{}'", function.name)),
+                _ => crate::warning(&format!("directive {:?} on function '{}' has no effect yet", directive, function.name)),
+            }
+        }
+        self.defaults(&function.name, &function.parameters)?;
+        for parameter in &function.parameters {
+            self.check_type(&parameter.typetype).map_err(|error| format!("{} for parameter '{}' of '{}'", error, parameter.name, function.name))?;
+            self.declare(&parameter.name, &parameter.typetype)?;
+            if parameter.typetype == Type::Matrix {
+                self.record_shape(&parameter.name, None);
+            }
+        }
+        let ((), mut lines) = self.capture(|generator| generator.statements(&function.statements))?;
+        if lines.last().is_some_and(|line| line == "Return") {
+            lines.pop();
+        }
+        if self.complex {
+            lines.insert(0, modes::COMPLEX.to_string());
+        }
+        Ok(Program { name: self.programs[&function.name].clone(), lines })
+    }
+
+    /// Checks that every default value is a constant of its parameter's type, as callers evaluate it again each time.
+    fn defaults(&self, owner: &str, parameters: &[Parameter]) -> Result<(), String> {
+        for parameter in parameters {
+            let Some(default) = &parameter.default else { continue };
+            if !is_constant(default) {
+                return Err(format!("the default value of '{}' in '{}' must be a constant on line {}", parameter.name, owner, default.line_number));
+            }
+            let actual = self.type_of(default)?;
+            if widen(&parameter.typetype, &actual).as_ref() != Some(&parameter.typetype) {
+                return Err(format!("the default value of '{}' in '{}' should be {:?}, but found {:?} on line {}", parameter.name, owner, parameter.typetype, actual, default.line_number));
+            }
+        }
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), String> {
+        let mut known = HashMap::new();
+        for (i, statement) in statements.iter().enumerate() {
+            self.known = known.clone();
+            self.statement(statement)?;
+            if self.after_exit(statement, &statements[i + 1..])? {
+                break;
+            }
+            match statement.type_.as_ref() {
+                StatementType::Assignment(target, value) | StatementType::Declaration(target, _, value) => match (target.type_.as_ref(), evaluate(value, &known)) {
+                    (ExpressionType::Field(name), Some(value)) => { known.insert(name.clone(), value); }
+                    _ => known.clear(),
+                },
+                StatementType::Comment(_) => {}
+                _ => known.clear(),
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        self.temps = 0;
+        let line = statement.line_number;
+        for directive in &statement.directives {
+            match directive {
+                Directive::Var(register) => self.pin(statement, register)?,
+                _ => crate::warning(&format!("directive {:?} on line {} has no effect yet", directive, line)),
+            }
+        }
+        for expression in expressions(statement) {
+            self.bind_comprehensions(expression)?;
+        }
+        match statement.type_.as_ref() {
+            StatementType::Assignment(target, value) => self.assignment(target, None, value),
+            StatementType::Declaration(target, type_, value) => self.assignment(target, Some(type_), value),
+            StatementType::If(condition, body, else_body) => self.if_statement(condition, body, else_body.as_ref()),
+            StatementType::While(condition, body) => {
+                let known = std::mem::take(&mut self.known);
+                self.while_loop(condition, body, &known)
+            }
+            StatementType::For(variable, list, body) => self.for_loop(variable, list, body, line),
+            StatementType::Return(value) => {
+                let function = self.current();
+                match (value, &function.return_type) {
+                    (Some(value), Some(expected @ Type::Tuple(types))) => {
+                        let actual = self.type_of(value)?;
+                        let fits = match &actual {
+                            Type::Tuple(actual) => actual.len() == types.len()
+                                && types.iter().zip(actual).all(|(expected, actual)| widen(expected, actual).as_ref() == Some(expected)),
+                            _ => false,
+                        };
+                        if !fits {
+                            return Err(format!("function '{}' returns {:?}, but found {:?} on line {}", function.name, expected, actual, line));
+                        }
+                        let returned = self.returned(&function.name, line)?;
+                        self.unpack(&returned, value)?;
+                    }
+                    (Some(value), Some(expected)) => {
+                        let actual = self.type_of(value)?;
+                        if widen(expected, &actual).as_ref() != Some(expected) {
+                            return Err(format!("function '{}' returns {:?}, but found {:?} on line {}", function.name, expected, actual, line));
+                        }
+                        let code = self.value(value)?;
+                        if code.text != "Ans" {
+                            self.lines.push(code.text);
+                        }
+                    }
+                    (None, None) => {}
+                    (Some(_), None) => return Err(format!("function '{}' does not declare a return type, but returns a value on line {}", function.name, line)),
+                    (None, Some(_)) => return Err(format!("function '{}' must return a value on line {}", function.name, line)),
+                }
+                self.lines.push("Return".to_string());
+                Ok(())
+            }
+            StatementType::Ignored(expression) => self.ignored(expression),
+            StatementType::Pass | StatementType::Global(_) => Ok(()),
+            StatementType::Break => self.exit_loop(true, line),
+            StatementType::Continue => self.exit_loop(false, line),
+            StatementType::Match(subject, cases) => self.match_statement(subject, cases),
+            StatementType::Try(body, handlers, otherwise, finally) => self.try_statement(body, handlers, otherwise, finally, line),
+            StatementType::Assert(condition, message) => self.assertion(condition, message.as_ref(), line),
+            StatementType::Del(target) => self.delete(target),
+            StatementType::Comment(text) => {
+                // a bare string is evaluated into Ans, which holds nothing we rely on between statements
+                if self.options.comments {
+                    self.lines.push(format!("\"{}", text.replace(['"', '→'], "'")));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Holds the variable assigned by `statement` in `register` rather than the next free TI-Basic variable.
+    fn pin(&mut self, statement: &Statement, register: &str) -> Result<(), String> {
+        let line = statement.line_number;
+        let name = match statement.type_.as_ref() {
+            StatementType::Assignment(target, _) | StatementType::Declaration(target, _, _) => match target.type_.as_ref() {
+                ExpressionType::Field(name) => name,
+                _ => return Err(format!("'var=' needs a plain variable to assign on line {}", line))
+            },
+            _ => return Err(format!("'var=' must come before an assignment on line {}", line))
+        };
+        if register_storage(register).is_none() {
+            return Err(format!("'{}' is not a TI-Basic variable on line {}", register, line));
+        }
+        let key = self.key(name);
+        if let Some(variable) = self.variables.get(&key) {
+            if variable.name != register {
+                return Err(format!("'{}' is already held in {}, so it cannot move to {} on line {}", name, variable.name, register, line));
+            }
+            return Ok(());
+        }
+        let holders = self.variables.iter().map(|(other, variable)| (other, &variable.name)).chain(self.pins.iter());
+        if let Some((other, _)) = holders.filter(|(other, _)| **other != key).find(|(_, held)| *held == register) {
+            return Err(format!("{} already holds '{}' on line {}", register, other.rsplit("::").next().unwrap(), line));
+        }
+        self.pins.insert(key, register.to_string());
+        Ok(())
+    }
+
+    fn assignment(&mut self, target: &Expression, declared: Option<&Type>, value: &Expression) -> Result<(), String> {
+        let line = target.line_number;
+        if let ExpressionType::Tuple(targets) = target.type_.as_ref() {
+            if declared.is_some() {
+                return Err(format!("only a single variable can be annotated on line {}", line));
+            }
+            return self.unpack(targets, value);
+        }
+        if let ExpressionType::Lambda(_, _) = value.type_.as_ref() {
+            let ExpressionType::Field(name) = target.type_.as_ref() else {
+                return Err(format!("a lambda can only be assigned to a variable on line {}", line));
+            };
+            let expected = match declared {
+                Some(declared) => declared.clone(),
+                None => match self.variables.get(&self.key(name)) {
+                    Some(variable) => variable.type_.clone(),
+                    None => return Err(format!("'{}' must be declared as a Callable to hold a lambda on line {}", name, line)),
+                },
+            };
+            self.check_type(&expected).map_err(|error| format!("{} on line {}", error, line))?;
+            let code = self.callable(value, &expected)?;
+            let variable = self.declare(name, &expected)?;
+            self.lines.push(format!("{}→{}", code.text, variable));
+            return Ok(());
+        }
+        let actual = self.type_of(value)?;
+        let empty = matches!(value.type_.as_ref(), ExpressionType::ListLiteral(items) if items.is_empty());
+        if let Some(declared) = declared {
+            self.check_type(declared).map_err(|error| format!("{} on line {}", error, line))?;
+            if storage(declared) != storage(&actual) && !empty {
+                return Err(format!("cannot assign {:?} to a variable declared as {:?} on line {}", actual, declared, line));
+            }
+        }
+        match target.type_.as_ref() {
+            ExpressionType::Field(name) if matches!(declared.unwrap_or(&actual), Type::RecordList(_)) => {
+                self.assign_records(name, declared.unwrap_or(&actual), value)
+            }
+            // TI-Basic has no empty list literal, so the list is emptied in place
+            ExpressionType::Field(name) if empty => {
+                let variable = self.declare(name, declared.unwrap_or(&actual))?;
+                if storage(&self.variables[name].type_) != Storage::List {
+                    return Err(format!("cannot assign an empty list to '{}' on line {}", name, line));
+                }
+                self.lines.push(format!("0→dim({})", variable));
+                Ok(())
+            }
+            ExpressionType::Field(name) => {
+                if actual == Type::Matrix {
+                    let shape = self.shape(value)?;
+                    self.record_shape(name, shape);
+                }
+                let code = self.value(value)?;
+                let variable = self.declare(name, declared.unwrap_or(&actual))?;
+                if code.text != variable {
+                    self.lines.push(format!("{}→{}", code.text, variable));
+                }
+                Ok(())
+            }
+            ExpressionType::Operation(Operation::ArrayIndex(list, index)) => {
+                let element = self.type_of(target)?;
+                if element == Type::String {
+                    return Err(format!("strings are immutable, cannot assign to an index on line {}", line));
+                }
+                if widen(&element, &actual).is_none() {
+                    return Err(format!("cannot store {:?} in an element of type {:?} on line {}", actual, element, line));
+                }
+                if let Type::Record(_) = element {
+                    return self.store_record(list, index, value);
+                }
+                let code = self.expression(value)?;
+                let element = self.element(list, index)?;
+                self.lines.push(format!("{}→{}", code.text, element.text));
+                Ok(())
+            }
+            ExpressionType::Attribute(receiver, name) if matches!(self.type_of(receiver)?, Type::Record(_)) => {
+                let field = self.type_of(target)?;
+                if widen(&field, &actual).as_ref() != Some(&field) {
+                    return Err(format!("cannot store {:?} in field '{}' of type {:?} on line {}", actual, name, field, line));
+                }
+                self.store_attribute(receiver, name, value)
+            }
+            _ => Err(format!("cannot assign to expression on line {}", line))
+        }
+    }
+
+    /// Lowers `a, b = value`, evaluating every value before assigning them from left to right.
+    fn unpack(&mut self, targets: &[Expression], value: &Expression) -> Result<(), String> {
+        let line = value.line_number;
+        let mut values = match value.type_.as_ref() {
+            ExpressionType::Tuple(items) => items.clone(),
+            ExpressionType::Call(name, args) if matches!(self.type_of(value)?, Type::Tuple(_)) => {
+                self.invoke(name, args, line)?;
+                self.returned(name, line)?
+            }
+            _ => return Err(format!("cannot unpack {:?} on line {}", self.type_of(value)?, line))
+        };
+        if values.len() != targets.len() {
+            return Err(format!("cannot unpack {} values into {} targets on line {}", values.len(), targets.len(), line));
+        }
+        // a value reading what an earlier target overwrites is evaluated into a temporary first, as in a, b = b, a
+        let mut written = Vec::new();
+        for (i, (target, value)) in targets.iter().zip(values.iter_mut()).enumerate() {
+            let mut reads = Vec::new();
+            self.reads(value, &mut reads);
+            if reads.iter().any(|read| written.contains(read)) {
+                let type_ = self.type_of(value)?;
+                let key = format!("{}$unpack{}{:?}", self.function, i, storage(&type_));
+                let code = self.expression(value)?;
+                let temp = self.declare(&key, &type_)?;
+                self.lines.push(format!("{}→{}", code.text, temp));
+                *value = Expression::new(ExpressionType::Field(key), line);
+            }
+            written.extend(self.written(target));
+        }
+        for (target, value) in targets.iter().zip(&values) {
+            self.assignment(target, None, value)?;
+        }
+        Ok(())
+    }
+
+    /// The variables a function returning a tuple leaves its values in, as `Ans` only holds one of them.
+    fn returned(&mut self, name: &str, line: usize) -> Result<Vec<Expression>, String> {
+        let functions = self.functions;
+        let Some(Type::Tuple(types)) = functions.iter().find(|function| function.name == name).and_then(|function| function.return_type.as_ref()) else {
+            return Err(format!("function '{}' does not return a tuple on line {}", name, line));
+        };
+        let mut returned = Vec::new();
+        for (i, type_) in types.iter().enumerate() {
+            let key = format!("{}$return{}", name, i);
+            self.declare(&key, type_)?;
+            returned.push(Expression::new(ExpressionType::Field(key), line));
+        }
+        Ok(returned)
+    }
+
+    /// The TI-Basic variable an assignment to `target` changes, if it already exists.
+    fn written(&self, target: &Expression) -> Option<String> {
+        match target.type_.as_ref() {
+            ExpressionType::Field(name) => self.variables.get(&self.key(name)).map(|variable| variable.name.clone()),
+            ExpressionType::Operation(Operation::ArrayIndex(inner, _)) | ExpressionType::Attribute(inner, _) => self.written(inner),
+            _ => None
+        }
+    }
+
+    fn if_statement(&mut self, condition: &Expression, body: &[Statement], else_body: Option<&Statement>) -> Result<(), String> {
+        // a condition folded to a constant, like `if DEBUG:`, leaves only the branch it takes
+        if let ExpressionType::BoolLiteral(truth) = condition.type_.as_ref() {
+            return match (truth, else_body) {
+                (true, _) => self.statements(body),
+                (false, Some(else_body)) => self.statement(else_body),
+                (false, None) => Ok(()),
+            };
+        }
+        match else_body {
+            None => {
+                let ((), body) = self.capture(|generator| generator.statements(body))?;
+                self.guarded(condition, body)
+            }
+            Some(else_body) => {
+                let code = self.condition(condition)?;
+                let ((), body) = self.capture(|generator| generator.statements(body))?;
+                let ((), else_body) = self.capture(|generator| match else_body.type_.as_ref() {
+                    StatementType::If(condition, body, None) if matches!(condition.type_.as_ref(), ExpressionType::BoolLiteral(true)) => {
+                        generator.statements(body)
+                    }
+                    _ => generator.statement(else_body)
+                })?;
+                self.block(format!("If {}", code.text), body, Some(else_body));
+                Ok(())
+            }
+        }
+    }
+
+    /// Emits `body` behind `condition`. TI-Basic evaluates both sides of `and`, so a right operand that could
+    /// error or has side effects is tested in a nested `If` instead, matching Python's short-circuiting.
+    fn guarded(&mut self, condition: &Expression, body: Vec<String>) -> Result<(), String> {
+        if let ExpressionType::Operation(Operation::And(left, right)) = condition.type_.as_ref() {
+            if !self.is_safe(right) {
+                let ((), inner) = self.capture(|generator| generator.guarded(right, body))?;
+                return self.guarded(left, inner);
+            }
+        }
+        let code = self.condition(condition)?;
+        self.block(format!("If {}", code.text), body, None);
+        Ok(())
+    }
+
+    fn block(&mut self, header: String, body: Vec<String>, else_body: Option<Vec<String>>) {
+        // an `If` with nothing under it would take the next line as its body
+        if body.is_empty() && else_body.is_none() {
+            return;
+        }
+        self.lines.push(header);
+        if else_body.is_none() && body.len() == 1 {
+            self.lines.extend(body);
+            return;
+        }
+        self.lines.push("Then".to_string());
+        self.lines.extend(body);
+        if let Some(else_body) = else_body {
+            self.lines.push("Else".to_string());
+            self.lines.extend(else_body);
+        }
+        self.lines.push("End".to_string());
+    }
+
+    fn ignored(&mut self, expression: &Expression) -> Result<(), String> {
+        let line = expression.line_number;
+        match expression.type_.as_ref() {
+            ExpressionType::Call(name, args) if self.programs.contains_key(name) => self.invoke(name, args, line),
+            ExpressionType::Call(name, args) if self.is_callable(name) => self.indirect(name, args, line).map(|_| ()),
+            ExpressionType::Call(name, args) => self.builtin_statement(name, args, line),
+            ExpressionType::Method(receiver, name, args) => self.method_statement(receiver, name, args, line),
+            ExpressionType::Operation(operation @ (Operation::Increment(target) | Operation::Decrement(target))) => {
+                let step = if matches!(operation, Operation::Increment(_)) { "+" } else { "-" };
+                let code = self.expression(target)?;
+                self.lines.push(format!("{}{}1→{}", code.text, step, code.text));
+                Ok(())
+            }
+            // docstrings
+            ExpressionType::StringLiteral(_) | ExpressionType::FormatString(_) | ExpressionType::Empty => Ok(()),
+            _ => Err(format!("expression statement has no effect on line {}", line))
+        }
+    }
+
+    /// Lowers an expression whose result is consumed immediately, letting a user function call leave its result in `Ans`.
+    fn value(&mut self, expression: &Expression) -> Result<Code, String> {
+        if let ExpressionType::Call(name, args) = expression.type_.as_ref() {
+            if self.programs.contains_key(name) {
+                self.invoke(name, args, expression.line_number)?;
+                return Ok(Code::atom("Ans".to_string()));
+            }
+            if self.is_callable(name) {
+                if self.indirect(name, args, expression.line_number)?.is_none() {
+                    return Err(format!("'{}' does not return a value on line {}", name, expression.line_number));
+                }
+                return Ok(Code::atom("Ans".to_string()));
+            }
+        }
+        self.expression(expression)
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<Code, String> {
+        let line = expression.line_number;
+        Ok(match expression.type_.as_ref() {
+            ExpressionType::IntLiteral(i) => integer(*i),
+            ExpressionType::FloatLiteral(f) => float(*f),
+            ExpressionType::ImaginaryLiteral(f) => {
+                self.complex = true;
+                imaginary(*f)
+            }
+            ExpressionType::BoolLiteral(b) => Code::atom(if *b { "1" } else { "0" }.to_string()),
+            ExpressionType::StringLiteral(s) => string(s, line)?,
+            ExpressionType::CharLiteral(c) => string(&c.to_string(), line)?,
+            ExpressionType::FormatString(parts) => self.format_string(parts, line)?,
+            ExpressionType::Field(name) if !self.variables.contains_key(&self.key(name)) && self.programs.contains_key(name) => self.reference(name, line)?,
+            ExpressionType::Field(name) => {
+                let variable = self.lookup(name, line)?;
+                if let Type::RecordList(_) = variable.type_ {
+                    return Err(format!("a list of records can only be indexed, iterated over or passed to a function on line {}", line));
+                }
+                Code::atom(variable.name.clone())
+            }
+            ExpressionType::ListLiteral(items) if items.is_empty() => {
+                return Err(format!("an empty list can only be assigned to a variable on line {}", line));
+            }
+            ExpressionType::Tuple(_) => {
+                return Err(format!("a tuple can only be returned or unpacked, as in a, b = b, a, on line {}", line));
+            }
+            ExpressionType::Keyword(name, _) => return Err(format!("unexpected keyword argument '{}' on line {}", name, line)),
+            ExpressionType::Lambda(_, _) => return Err(format!("a lambda can only be passed or assigned where a Callable is expected on line {}", line)),
+            ExpressionType::ListLiteral(items) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(self.expression(item)?.text);
+                }
+                Code::atom(format!("{{{}}}", values.join(",")))
+            }
+            ExpressionType::Call(name, _) if self.record(name).is_some() => {
+                self.type_of(expression)?;
+                self.pack(expression)?
+            }
+            ExpressionType::Call(name, args) if self.is_callable(name) => {
+                let Some(type_) = self.indirect(name, args, line)? else {
+                    return Err(format!("'{}' does not return a value on line {}", name, line));
+                };
+                let temp = self.temp(&type_)?;
+                self.lines.push(format!("Ans→{}", temp));
+                Code::atom(temp)
+            }
+            ExpressionType::Call(name, args) if !self.programs.contains_key(name) => self.builtin(name, args, line)?,
+            ExpressionType::Method(receiver, name, args) => self.method(receiver, name, args, line)?,
+            ExpressionType::Attribute(receiver, name) => self.attribute(receiver, name, line)?,
+            ExpressionType::Call(name, args) => {
+                let type_ = self.type_of(expression)?;
+                if let Type::Tuple(_) = type_ {
+                    return Err(format!("the tuple returned by '{}' must be unpacked, as in a, b = {}(), on line {}", name, name, line));
+                }
+                self.invoke(name, args, line)?;
+                let temp = self.temp(&type_)?;
+                self.lines.push(format!("Ans→{}", temp));
+                Code::atom(temp)
+            }
+            // the shapes are checked here too, as a matrix operation that is only displayed is never typed
+            ExpressionType::Operation(operation) if self.is_matrix_operation(operation)? => {
+                self.shape(expression)?;
+                self.operation(operation, line)?
+            }
+            ExpressionType::Operation(operation) => self.operation(operation, line)?,
+            ExpressionType::Comparison(operands, operators) => self.comparison(operands, operators)?,
+            ExpressionType::Comprehension(element, variable, iterable) => self.comprehension(element, variable, iterable, line)?,
+            ExpressionType::Slice(value, start, stop, step) => self.slice(value, start.as_ref(), stop.as_ref(), step.as_ref(), line)?,
+            ExpressionType::Conditional(condition, value, other) => {
+                let type_ = self.type_of(expression)?;
+                self.conditional(condition, value, other, &type_)?
+            }
+            ExpressionType::Empty => return Err(format!("expected expression on line {}", line)),
+        })
+    }
+
+    /// Lowers an f-string to a concatenation, converting interpolated numbers to strings.
+    fn format_string(&mut self, parts: &[Expression], line: usize) -> Result<Code, String> {
+        let mut pieces: Vec<Code> = Vec::new();
+        for part in parts {
+            let type_ = self.type_of(part)?;
+            let piece = match (part.type_.as_ref(), &type_) {
+                (ExpressionType::IntLiteral(i), _) => string(&integer(*i).text, line)?,
+                (_, Type::String) => self.expression(part)?,
+                (_, Type::Bool) => {
+                    let code = self.expression(part)?;
+                    bool_string(&code)
+                }
+                (_, Type::Int | Type::Float) => {
+                    let code = self.expression(part)?;
+                    self.number_string(code)?
+                }
+                _ => return Err(format!("cannot interpolate {:?} into an f-string on line {}", type_, line))
+            };
+            // adjacent literals are merged into one
+            match pieces.last_mut() {
+                Some(last) if is_literal(&last.text) && is_literal(&piece.text) => {
+                    last.text = format!("{}{}", &last.text[..last.text.len() - 1], &piece.text[1..]);
+                }
+                _ => pieces.push(piece),
+            }
+        }
+        Ok(match pieces.len() {
+            0 => Code::atom("\"\"".to_string()),
+            1 => pieces.remove(0),
+            _ => Code::new(pieces.iter().map(|piece| piece.wrap(ADDITIVE + 1)).collect::<Vec<String>>().join("+"), ADDITIVE),
+        })
+    }
+
+    /// Converts a real number to a string. TI-84 models without `toString(` have no direct way of doing so,
+    /// so a helper program stores the value as the slope of a regression equation and reads it back with
+    /// `Equ►String(`, leaving the result in `Ans`. This clobbers `L₁`, `L₂` and `Y₁`.
+    fn number_string(&mut self, code: Code) -> Result<Code, String> {
+        let value = self.declare("$string$value", &Type::Float)?;
+        let call = self.helper("CRSTR", |generator| {
+            let text = generator.declare("$string$text", &Type::String)?;
+            Ok(vec![
+                "{0,1}→L₁".to_string(),
+                format!("{{0,{}}}→L₂", value),
+                "LinReg(ax+b) Y₁".to_string(),
+                format!("Equ►String(Y₁,{})", text),
+                format!("sub({},1,length({})-3)", text, text),
+            ])
+        })?;
+        self.lines.push(format!("{}→{}", code.text, value));
+        self.lines.push(call);
+        let temp = self.temp(&Type::String)?;
+        self.lines.push(format!("Ans→{}", temp));
+        Ok(Code::atom(temp))
+    }
+
+    /// Lowers `a < b < c` to `a<b and b<c`, storing any middle operand that isn't a plain value in a temporary
+    /// so it's evaluated once. Operands that could error are only evaluated once the earlier comparisons hold.
+    fn comparison(&mut self, operands: &[Expression], operators: &[Operator]) -> Result<Code, String> {
+        let left = self.expression(&operands[0])?;
+        if operands.iter().all(|operand| self.is_safe(operand)) {
+            let mut left = left;
+            let mut comparisons = Vec::new();
+            for (i, operator) in operators.iter().enumerate() {
+                let right = self.middle(&operands[i + 1], i + 1 < operators.len())?;
+                comparisons.push(format!("{}{}{}", left.wrap(RELATIONAL), relational(operator), right.wrap(RELATIONAL + 1)));
+                left = right;
+            }
+            return Ok(Code::new(comparisons.join(" and "), AND));
+        }
+        let result = self.temp(&Type::Int)?;
+        self.lines.push(format!("0→{}", result));
+        self.comparison_step(left, &operands[1..], operators, &result)?;
+        Ok(Code::atom(result))
+    }
+
+    fn comparison_step(&mut self, left: Code, operands: &[Expression], operators: &[Operator], result: &str) -> Result<(), String> {
+        let right = self.middle(&operands[0], operators.len() > 1)?;
+        let comparison = format!("{}{}{}", left.wrap(RELATIONAL), relational(&operators[0]), right.wrap(RELATIONAL + 1));
+        if operators.len() == 1 {
+            self.lines.push(format!("{}→{}", comparison, result));
+            return Ok(());
+        }
+        let ((), body) = self.capture(|generator| generator.comparison_step(right, &operands[1..], &operators[1..], result))?;
+        self.block(format!("If {}", comparison), body, None);
+        Ok(())
+    }
+
+    fn middle(&mut self, operand: &Expression, reused: bool) -> Result<Code, String> {
+        let code = self.expression(operand)?;
+        if !reused || code.precedence == ATOM && !self.varies(operand) {
+            return Ok(code);
+        }
+        let temp = self.temp(&self.type_of(operand)?)?;
+        self.lines.push(format!("{}→{}", code.text, temp));
+        Ok(Code::atom(temp))
+    }
+
+    /// Lowers `value if condition else other`. Real values whose evaluation is harmless use the boolean
+    /// arithmetic `value*(condition)+other*not(condition)`, everything else a temporary assigned in an `If` block.
+    fn conditional(&mut self, condition: &Expression, value: &Expression, other: &Expression, type_: &Type) -> Result<Code, String> {
+        let condition_code = self.condition(condition)?;
+        let truth = self.truthy(condition, &condition_code)?;
+        if storage(type_) == Storage::Real && self.is_safe(value) && self.is_safe(other) {
+            let mut terms = Vec::new();
+            // a truth value is already 0 or 1, so multiplying it by 1 is left out
+            if integer_literal(value) == Some(1) {
+                terms.push(truth.wrap(ATOM));
+            } else if !is_zero(value) {
+                terms.push(format!("{}*{}", self.expression(value)?.wrap(MULTIPLICATIVE), truth.wrap(ATOM)));
+            }
+            if integer_literal(other) == Some(1) {
+                terms.push(format!("not({})", truth.text));
+            } else if !is_zero(other) {
+                terms.push(format!("{}*not({})", self.expression(other)?.wrap(MULTIPLICATIVE), truth.text));
+            }
+            return Ok(match terms.len() {
+                0 => Code::atom("0".to_string()),
+                1 => Code::new(terms.remove(0), MULTIPLICATIVE),
+                _ => Code::new(terms.join("+"), ADDITIVE),
+            });
+        }
+        let temp = self.temp(type_)?;
+        let (value_code, mut body) = self.capture(|generator| generator.value(value))?;
+        body.push(format!("{}→{}", value_code.text, temp));
+        let (other_code, mut else_body) = self.capture(|generator| generator.value(other))?;
+        else_body.push(format!("{}→{}", other_code.text, temp));
+        self.block(format!("If {}", condition_code.text), body, Some(else_body));
+        Ok(Code::atom(temp))
+    }
+
+    /// Lowers a condition, which TI-Basic treats as true when it is a nonzero real number.
+    fn condition(&mut self, condition: &Expression) -> Result<Code, String> {
+        let type_ = self.type_of(condition)?;
+        if !matches!(type_, Type::Bool | Type::Int | Type::Float) {
+            return Err(format!("a condition must be a bool or a real number, but found {:?} on line {}", type_, condition.line_number));
+        }
+        self.expression(condition)
+    }
+
+    /// Coerces a lowered expression into TI-Basic's 0/1 truth values.
+    fn truthy(&self, expression: &Expression, code: &Code) -> Result<Code, String> {
+        Ok(if is_boolean(expression) || self.type_of(expression)? == Type::Bool {
+            code.clone()
+        } else {
+            Code::new(format!("{}≠0", code.wrap(RELATIONAL)), RELATIONAL)
+        })
+    }
+
+    fn operation(&mut self, operation: &Operation, line: usize) -> Result<Code, String> {
+        match operation {
+            // Python concatenates lists where TI-Basic would add them element by element
+            Operation::Add(l, r) if storage(&self.type_of(l)?) == Storage::List && storage(&self.type_of(r)?) == Storage::List => {
+                let (l, r) = (self.expression(l)?, self.expression(r)?);
+                Ok(Code::atom(format!("augment({},{})", l.text, r.text)))
+            }
+            Operation::Add(l, r) => self.binary(l, r, "+", ADDITIVE),
+            Operation::Sub(l, r) => self.binary(l, r, "-", ADDITIVE),
+            Operation::Mul(l, r) | Operation::MatMul(l, r) => self.binary(l, r, "*", MULTIPLICATIVE),
+            Operation::Div(l, r) => self.binary(l, r, "/", MULTIPLICATIVE),
+            Operation::FloorDiv(l, r) => {
+                // int( floors towards negative infinity, just like Python's //
+                let quotient = self.binary(l, r, "/", MULTIPLICATIVE)?;
+                Ok(Code::atom(format!("int({})", quotient.text)))
+            }
+            Operation::Mod(l, r) => {
+                // a-b*int(a/b) takes the sign of the divisor, unlike remainder(
+                let dividend = self.expression(l)?;
+                let divisor = self.expression(r)?;
+                Ok(Code::new(format!("{}-{}*int({}/{})",
+                    dividend.wrap(ADDITIVE), divisor.wrap(MULTIPLICATIVE), dividend.wrap(MULTIPLICATIVE), divisor.wrap(MULTIPLICATIVE + 1)), ADDITIVE))
+            }
+            Operation::Pow(l, r) => {
+                let base = self.expression(l)?;
+                if integer_literal(r) == Some(-1) {
+                    return Ok(Code::new(format!("{}⁻¹", base.wrap(POSTFIX)), POSTFIX));
+                }
+                Ok(match r.type_.as_ref() {
+                    ExpressionType::IntLiteral(2) => Code::new(format!("{}²", base.wrap(POSTFIX)), POSTFIX),
+                    ExpressionType::IntLiteral(3) => Code::new(format!("{}³", base.wrap(POSTFIX)), POSTFIX),
+                    _ => {
+                        // TI-Basic's ^ is left associative while Python's ** is right associative
+                        let exponent = self.expression(r)?;
+                        Code::new(format!("{}^{}", base.wrap(POWER), exponent.wrap(POWER + 1)), POWER)
+                    }
+                })
+            }
+            Operation::Equals(l, r) => self.binary(l, r, "=", RELATIONAL),
+            Operation::NotEqual(l, r) => self.binary(l, r, "≠", RELATIONAL),
+            Operation::GreaterThan(l, r) => self.binary(l, r, ">", RELATIONAL),
+            Operation::LessThan(l, r) => self.binary(l, r, "<", RELATIONAL),
+            Operation::GreaterEquals(l, r) => self.binary(l, r, "≥", RELATIONAL),
+            Operation::LessEquals(l, r) => self.binary(l, r, "≤", RELATIONAL),
+            Operation::And(l, r) => self.logical(l, r, true),
+            Operation::Or(l, r) => self.logical(l, r, false),
+            Operation::Not(e) => Ok(Code::atom(format!("not({})", self.expression(e)?.text))),
+            Operation::Negative(e) => Ok(Code::new(format!("⁻{}", self.expression(e)?.wrap(NEGATION)), NEGATION)),
+            Operation::ArrayIndex(list, index) => self.element(list, index),
+            Operation::In(needle, haystack) => {
+                let string = self.type_of(haystack)? == Type::String;
+                let needle = self.expression(needle)?;
+                let haystack = self.expression(haystack)?;
+                if string {
+                    Ok(Code::new(format!("inString({},{})≠0", haystack.text, needle.text), RELATIONAL))
+                } else {
+                    // comparing a list with a value gives a list of 0s and 1s
+                    Ok(Code::atom(format!("max({}={})", haystack.wrap(RELATIONAL + 1), needle.wrap(RELATIONAL + 1))))
+                }
+            }
+            Operation::LBS(l, r) => {
+                let value = self.expression(l)?;
+                let scale = self.power_of_two(r, line)?;
+                Ok(Code::new(format!("{}*{}", value.wrap(MULTIPLICATIVE), scale.wrap(MULTIPLICATIVE + 1)), MULTIPLICATIVE))
+            }
+            Operation::RBS(l, r) => {
+                let value = self.expression(l)?;
+                let scale = self.power_of_two(r, line)?;
+                Ok(Code::atom(format!("int({}/{})", value.wrap(MULTIPLICATIVE), scale.wrap(MULTIPLICATIVE + 1))))
+            }
+            // on two bools the bitwise operators are TI-Basic's logical ones, which also evaluate both sides
+            Operation::BinaryAnd(l, r) | Operation::BinaryOr(l, r) | Operation::BinaryXor(l, r)
+                if self.type_of(l)? == Type::Bool && self.type_of(r)? == Type::Bool => {
+                let (symbol, precedence) = match operation {
+                    Operation::BinaryAnd(_, _) => (" and ", AND),
+                    Operation::BinaryOr(_, _) => (" or ", OR),
+                    _ => (" xor ", OR),
+                };
+                self.binary(l, r, symbol, precedence)
+            }
+            Operation::BinaryAnd(l, r) => self.bitwise(l, r, "and", line),
+            Operation::BinaryOr(l, r) => self.bitwise(l, r, "or", line),
+            Operation::BinaryXor(l, r) => self.bitwise(l, r, "xor", line),
+            Operation::BinaryNot(e) => Ok(Code::new(format!("⁻{}-1", self.expression(e)?.wrap(NEGATION)), ADDITIVE)),
+            o => Err(format!("operator '{}' is not supported on line {}", o, line)),
+        }
+    }
+
+    fn binary(&mut self, left: &Expression, right: &Expression, symbol: &str, precedence: u8) -> Result<Code, String> {
+        let left = self.expression(left)?;
+        let right = self.expression(right)?;
+        Ok(Code::new(format!("{}{}{}", left.wrap(precedence), symbol, right.wrap(precedence + 1)), precedence))
+    }
+
+    /// Lowers `and`/`or`. The infix tokens are only used when evaluating the right operand unconditionally is
+    /// harmless, otherwise the result is computed into a temporary behind an `If` on the left operand.
+    fn logical(&mut self, left: &Expression, right: &Expression, and: bool) -> Result<Code, String> {
+        let left_code = self.expression(left)?;
+        let (right_code, setup) = self.capture(|generator| generator.expression(right))?;
+        if setup.is_empty() && self.is_safe(right) {
+            let (symbol, precedence) = if and { (" and ", AND) } else { (" or ", OR) };
+            return Ok(Code::new(format!("{}{}{}", left_code.wrap(precedence), symbol, right_code.wrap(precedence + 1)), precedence));
+        }
+        let temp = self.temp(&Type::Int)?;
+        let mut body = setup;
+        body.push(format!("{}→{}", self.truthy(right, &right_code)?.text, temp));
+        if and {
+            self.lines.push(format!("0→{}", temp));
+            self.block(format!("If {}", left_code.text), body, None);
+        } else {
+            self.lines.push(format!("1→{}", temp));
+            self.block(format!("If not({})", left_code.text), body, None);
+        }
+        Ok(Code::atom(temp))
+    }
+
+    /// `2^exponent`, folded into a literal when the shift amount is constant. Python raises ValueError for a negative
+    /// shift, where `2^` would give a fraction, so a shift amount that could be negative is checked at runtime, in
+    /// release builds too.
+    fn power_of_two(&mut self, exponent: &Expression, line: usize) -> Result<Code, String> {
+        match integer_literal(exponent) {
+            Some(i) if i < 0 => return Err(format!("negative shift count on line {}", line)),
+            Some(i) if i < 47 => return Ok(integer(1 << i)),
+            _ => {}
+        }
+        let mut code = self.expression(exponent)?;
+        if !is_natural(exponent) {
+            if !matches!(exponent.type_.as_ref(), ExpressionType::Field(_)) {
+                let temp = self.temp(&Type::Int)?;
+                self.lines.push(format!("{}→{}", code.text, temp));
+                code = Code::atom(temp);
+            }
+            self.failure(format!("{}<0", code.wrap(RELATIONAL + 1)), "ValueError", line);
+        }
+        Ok(Code::new(format!("2^{}", code.wrap(POWER + 1)), POWER))
+    }
+
+    /// TI-Basic has no bitwise operators, so `&`, `|` and `^` call a helper program that walks the binary digits
+    /// of both operands, leaving the result in `Ans`. `int(` rounds down, so a negative operand shifts towards ⁻1,
+    /// and once both operands are 0 or ⁻1 their sign bits give every remaining digit, as in two's complement.
+    fn bitwise(&mut self, left: &Expression, right: &Expression, operator: &str, line: usize) -> Result<Code, String> {
+        crate::warning(&format!("bitwise '{}' on line {} is emulated by a digit-loop helper program, which is slow and adds to program size", operator, line));
+        let left = self.expression(left)?;
+        let right = self.expression(right)?;
+        let x = self.declare("$bits$left", &Type::Int)?;
+        let y = self.declare("$bits$right", &Type::Int)?;
+        let call = self.helper(&format!("CR{}", operator.to_uppercase()), |generator| {
+            let result = generator.declare("$bits$result", &Type::Int)?;
+            let place = generator.declare("$bits$place", &Type::Int)?;
+            Ok(vec![
+                format!("0→{}", result),
+                format!("1→{}", place),
+                format!("While {}({}+1) or {}({}+1)", x, x, y, y),
+                format!("{}+{}*(fPart({}/2) {} fPart({}/2))→{}", result, place, x, operator, y, result),
+                format!("int({}/2)→{}", x, x),
+                format!("int({}/2)→{}", y, y),
+                format!("2*{}→{}", place, place),
+                "End".to_string(),
+                format!("{}-{}*({} {} {})", result, place, x, operator, y),
+            ])
+        })?;
+        self.lines.push(format!("{}→{}", left.text, x));
+        self.lines.push(format!("{}→{}", right.text, y));
+        self.lines.push(call);
+        let temp = self.temp(&Type::Int)?;
+        self.lines.push(format!("Ans→{}", temp));
+        Ok(Code::atom(temp))
+    }
+
+    /// Declares the variables of any list comprehensions in `expression`, so they can be typed before lowering.
+    fn bind_comprehensions(&mut self, expression: &Expression) -> Result<(), String> {
+        if let ExpressionType::Comprehension(element, variable, iterable) = expression.type_.as_ref() {
+            self.bind_comprehensions(iterable)?;
+            let type_ = match range(iterable) {
+                Some(_) => Type::Int,
+                None => match element_type(&self.type_of(iterable)?) {
+                    Some(type_) => type_,
+                    None => return Err(format!("list comprehensions can only loop over range() or a list on line {}", expression.line_number))
+                }
+            };
+            self.declare(variable, &type_)?;
+            return self.bind_comprehensions(element);
+        }
+        children(expression).into_iter().try_for_each(|child| self.bind_comprehensions(child))
+    }
+
+    /// Lowers `[element for variable in iterable]` to `seq(`, which can only hold a single expression.
+    fn comprehension(&mut self, element: &Expression, variable: &str, iterable: &Expression, line: usize) -> Result<Code, String> {
+        let name = self.lookup(variable, line)?.name.clone();
+        let (code, setup) = match range(iterable) {
+            Some(args) => {
+                let (start, end, step) = match args {
+                    [end] => (integer(0), self.offset(end, -1)?, None),
+                    [start, end] => (self.expression(start)?, self.offset(end, -1)?, None),
+                    [start, end, step] => {
+                        let direction = match integer_literal(step) {
+                            Some(step) if step > 0 => -1,
+                            Some(step) if step < 0 => 1,
+                            _ => return Err(format!("the step of range() in a list comprehension must be a non-zero literal on line {}", line))
+                        };
+                        (self.expression(start)?, self.offset(end, direction)?, Some(self.expression(step)?))
+                    }
+                    _ => return Err(format!("range() takes 1 to 3 arguments on line {}", line))
+                };
+                let (code, setup) = self.unchecked(|generator| generator.capture(|generator| generator.expression(element)))?;
+                let step = step.map(|step| format!(",{}", step.text)).unwrap_or_default();
+                (format!("seq({},{},{},{}{})", code.text, name, start.text, end.text, step), setup)
+            }
+            None => {
+                // the variable stands for the current element while the body is lowered
+                let list = self.expression(iterable)?;
+                let index = self.declare(&format!("{}$index{}", self.function, self.loops), &Type::Int)?;
+                let key = self.key(variable);
+                let element_name = std::mem::replace(&mut self.variables.get_mut(&key).unwrap().name, format!("{}({})", list.text, index));
+                self.loops += 1;
+                let lowered = self.unchecked(|generator| generator.capture(|generator| generator.expression(element)));
+                self.loops -= 1;
+                self.variables.get_mut(&key).unwrap().name = element_name;
+                let (code, setup) = lowered?;
+                (format!("seq({},{},1,dim({}))", code.text, index, list.text), setup)
+            }
+        };
+        if !setup.is_empty() {
+            return Err(format!("this list comprehension cannot be written as a single seq(, use a for loop instead on line {}", line));
+        }
+        Ok(Code::atom(code))
+    }
+
+    /// Lowers `expression + delta`, folding the constant into integer literals and additions like `i - 1`.
+    fn offset(&mut self, expression: &Expression, delta: i64) -> Result<Code, String> {
+        Ok(self.bound(expression, delta)?.code())
+    }
+
+    fn bound(&mut self, expression: &Expression, delta: i64) -> Result<Bound, String> {
+        match expression.type_.as_ref() {
+            ExpressionType::IntLiteral(i) => return Ok(Bound { term: None, constant: i + delta }),
+            ExpressionType::Operation(Operation::Add(inner, constant)) => if let Some(constant) = integer_literal(constant) {
+                return self.bound(inner, delta + constant);
+            },
+            ExpressionType::Operation(Operation::Sub(inner, constant)) => if let Some(constant) = integer_literal(constant) {
+                return self.bound(inner, delta - constant);
+            },
+            _ => {}
+        }
+        Ok(Bound { term: Some(self.expression(expression)?), constant: delta })
+    }
+
+    /// Like `bound`, but with a negative index counting back from `length` as Python indices do. Only an index that
+    /// could be negative at runtime pays for the check.
+    fn position(&mut self, expression: &Expression, length: &str, delta: i64) -> Result<Bound, String> {
+        match integer_literal(expression) {
+            Some(i) if i < 0 => return Ok(Bound { term: Some(Code::atom(length.to_string())), constant: i + delta }),
+            Some(_) => return self.bound(expression, delta),
+            None if is_natural(expression) => return self.bound(expression, delta),
+            None => {}
+        }
+        // the index is read twice, so anything but a variable is evaluated once into a temporary
+        let mut code = self.expression(expression)?;
+        if !matches!(expression.type_.as_ref(), ExpressionType::Field(_)) {
+            let temp = self.temp(&Type::Int)?;
+            self.lines.push(format!("{}→{}", code.text, temp));
+            code = Code::atom(temp);
+        }
+        let counted = Code::new(format!("{}+({}<0){}", code.wrap(ADDITIVE), code.wrap(RELATIONAL + 1), length), ADDITIVE);
+        Ok(Bound { term: Some(counted), constant: delta })
+    }
+
+    /// Lowers `value[start:stop:step]` to `sub(` for strings and `seq(` for lists.
+    fn slice(&mut self, value: &Expression, start: Option<&Expression>, stop: Option<&Expression>, step: Option<&Expression>, line: usize) -> Result<Code, String> {
+        let type_ = self.type_of(value)?;
+        let step = match step.map(integer_literal) {
+            None => 1,
+            Some(Some(step)) if step != 0 => step,
+            Some(_) => return Err(format!("the step of a slice must be a non-zero integer literal on line {}", line))
+        };
+        let known = match value.type_.as_ref() {
+            ExpressionType::ListLiteral(items) => Some(items.len() as i64),
+            ExpressionType::StringLiteral(text) => Some(text.chars().count() as i64),
+            _ => None,
+        };
+        check_slice(start.map(integer_literal), stop.map(integer_literal), step, known, line)?;
+        if type_ == Type::String {
+            if step != 1 {
+                return Err(format!("string slices cannot have a step on line {}", line));
+            }
+            let string = self.expression(value)?;
+            let length = format!("length({})", string.text);
+            let from = match start {
+                Some(start) => self.slice_bound(start, &length, 1, true)?,
+                None if stop.is_none() => return Ok(string),
+                None => Bound { term: None, constant: 1 },
+            };
+            let to = match stop {
+                Some(stop) => self.slice_bound(stop, &length, 0, false)?,
+                None => Bound { term: Some(Code::atom(length)), constant: 0 },
+            };
+            return Ok(Code::atom(format!("sub({},{},{})", string.text, from.clone().code().text, Bound::span(from, to).text)));
+        }
+        if storage(&type_) != Storage::List {
+            return Err(format!("cannot slice {:?} on line {}", type_, line));
+        }
+        // seq( reads elements through a list variable
+        let list = match value.type_.as_ref() {
+            ExpressionType::Field(name) => self.lookup(name, line)?.name.clone(),
+            _ => {
+                let code = self.expression(value)?;
+                let temp = self.temp(&type_)?;
+                self.lines.push(format!("{}→{}", code.text, temp));
+                temp
+            }
+        };
+        if step == 1 && start.is_none() && stop.is_none() {
+            return Ok(Code::atom(list));
+        }
+        let length = format!("dim({})", list);
+        let last = Bound { term: Some(Code::atom(length.clone())), constant: 0 };
+        let first = Bound { term: None, constant: 1 };
+        let (from, to) = if step > 0 {
+            (start.map(|start| self.slice_bound(start, &length, 1, true)).transpose()?.unwrap_or(first),
+             stop.map(|stop| self.slice_bound(stop, &length, 0, false)).transpose()?.unwrap_or(last))
+        } else {
+            (start.map(|start| self.slice_bound(start, &length, 1, false)).transpose()?.unwrap_or(last),
+             stop.map(|stop| self.slice_bound(stop, &length, 2, true)).transpose()?.unwrap_or(first))
+        };
+        let index = self.declare(&format!("{}$index{}", self.function, self.loops), &Type::Int)?;
+        let step = if step == 1 { String::new() } else { format!(",{}", integer(step).text) };
+        Ok(Code::atom(format!("seq({}({}),{},{},{}{})", list, index, index, from.code().text, to.code().text, step)))
+    }
+
+    /// Lowers a slice bound to the TI index `delta` past it, counting a negative bound back from `length` and clamping
+    /// it the way Python does. A `low` bound is kept at 1 or more, any other at most `length`. Bounds are only clamped
+    /// where they could pass that end, as past the other one the slice is empty and fails regardless.
+    fn slice_bound(&mut self, bound: &Expression, length: &str, delta: i64, low: bool) -> Result<Bound, String> {
+        let literal = integer_literal(bound);
+        let index = self.position(bound, length, delta)?;
+        let clamped = match (low, literal) {
+            (true, Some(i)) if i >= 0 => return Ok(index),
+            (false, Some(i)) if i < 0 => return Ok(index),
+            (true, _) => format!("max(1,{})", index.code().text),
+            (false, _) => format!("min({},{})", index.code().text, length),
+        };
+        Ok(Bound { term: Some(Code::atom(clamped)), constant: 0 })
+    }
+
+    fn element(&mut self, list: &Expression, index: &Expression) -> Result<Code, String> {
+        let line = list.line_number;
+        if let ExpressionType::Operation(Operation::ArrayIndex(matrix, row)) = list.type_.as_ref() {
+            if self.type_of(matrix)? == Type::Matrix {
+                return self.matrix_element(matrix, row, index);
+            }
+        }
+        if let Type::RecordList(_) = self.type_of(list)? {
+            let element = Expression::new(ExpressionType::Operation(Operation::ArrayIndex(list.clone(), index.clone())), line);
+            return self.pack(&element);
+        }
+        if self.type_of(list)? == Type::String {
+            let string = self.expression(list)?;
+            let index = self.index(index, &format!("length({})", string.text), line)?;
+            return Ok(Code::atom(format!("sub({},{},1)", string.text, index.text)));
+        }
+        let name = match list.type_.as_ref() {
+            ExpressionType::Field(name) => name,
+            _ => return Err(format!("only list variables can be indexed on line {}", line))
+        };
+        let variable = self.lookup(name, line)?;
+        if storage(&variable.type_) != Storage::List {
+            return Err(format!("cannot index '{}' of type {:?} on line {}", name, variable.type_, line));
+        }
+        let list = variable.name.clone();
+        let index = self.index(index, &format!("dim({})", list), line)?;
+        Ok(Code::atom(format!("{}({})", list, index.text)))
+    }
+
+    /// Lowers `del target`, moving every element after the deleted one down a place and dropping the last. A list
+    /// of records moves the elements of all its field lists in one loop.
+    fn delete(&mut self, target: &Expression) -> Result<(), String> {
+        let line = target.line_number;
+        let (list, index) = match target.type_.as_ref() {
+            ExpressionType::Tuple(targets) => return targets.iter().try_for_each(|target| self.delete(target)),
+            ExpressionType::Operation(Operation::ArrayIndex(list, index)) => (list, index),
+            _ => return Err(format!("only list elements like 'del xs[i]' can be deleted on line {}", line))
+        };
+        let type_ = self.type_of(list)?;
+        let lists = match (list.type_.as_ref(), &type_) {
+            (ExpressionType::Field(name), Type::RecordList(_)) => self.field_lists(name, line)?,
+            (ExpressionType::Field(name), Type::IntList | Type::FloatList | Type::ComplexList) => vec![self.lookup(name, line)?.name.clone()],
+            (_, Type::String) => return Err(format!("strings cannot be changed in place on line {}", line)),
+            _ => return Err(format!("only elements of list variables can be deleted on line {}", line))
+        };
+        let length = format!("dim({})", lists[0]);
+        let position = self.index(index, &length, line)?;
+        // deleting the last element leaves nothing to move, and a one-element list none to move either, as For(
+        // skips a loop whose start is past its end
+        if integer_literal(index) != Some(-1) {
+            let counter = self.declare(&format!("{}$index{}", self.function, self.loops), &Type::Int)?;
+            self.lines.push(format!("For({},{},{}-1)", counter, position.text, length));
+            for list in &lists {
+                self.lines.push(format!("{}({}+1)→{}({})", list, counter, list, counter));
+            }
+            self.lines.push("End".to_string());
+        }
+        for list in &lists {
+            self.lines.push(format!("dim({})-1→dim({})", list, list));
+        }
+        Ok(())
+    }
+
+    /// Translates a 0-based Python index into TI-Basic's 1-based indexing, where a negative index counts back
+    /// from `length`. Debug builds also check the index is in range, as TI-Basic would only report ERR:INVALID DIM.
+    fn index(&mut self, index: &Expression, length: &str, line: usize) -> Result<Code, String> {
+        let literal = integer_literal(index);
+        let code = self.position(index, length, 1)?.code();
+        if self.checking {
+            let condition = match literal {
+                Some(i) if i < 0 => format!("{}<{}", length, -i),
+                Some(i) => format!("{}<{}", length, i + 1),
+                None => format!("{}<1 or {}>{}", code.wrap(RELATIONAL + 1), code.wrap(RELATIONAL + 1), length),
+            };
+            self.failure(condition, "IndexError", line);
+        }
+        Ok(code)
+    }
+
+    /// Stops the program with a Python-style error and its line number when `condition` holds at runtime.
+    fn failure(&mut self, condition: String, error: &str, line: usize) {
+        self.stop(condition, format!("\"{}\"", error), line);
+    }
+
+    /// Stops the program when `condition` holds, displaying `message` and where in the Python source it stopped.
+    fn stop(&mut self, condition: String, message: String, line: usize) {
+        let file = &self.current().file;
+        self.lines.push(format!("If {}", condition));
+        self.lines.push("Then".to_string());
+        self.lines.push(format!("Disp {},\"{} LINE {}\"", message, file, line));
+        self.lines.push("Stop".to_string());
+        self.lines.push("End".to_string());
+    }
+
+    /// Lowers `assert condition, message` into a check that only debug builds contain, so release builds
+    /// do not evaluate the condition at all, as with Python's -O.
+    fn assertion(&mut self, condition: &Expression, message: Option<&Expression>, line: usize) -> Result<(), String> {
+        if !self.checking {
+            return Ok(());
+        }
+        let failed = Expression::new(ExpressionType::Operation(negation(condition)), line);
+        let code = self.condition(&failed)?;
+        let message = match message {
+            Some(message) => {
+                let type_ = self.type_of(message)?;
+                if !matches!(storage(&type_), Storage::String | Storage::Real) {
+                    return Err(format!("an assert message must be a string or a number, but found {:?} on line {}", type_, line));
+                }
+                format!("\"AssertionError\",{}", self.expression(message)?.text)
+            }
+            None => "\"AssertionError\"".to_string(),
+        };
+        self.stop(code.text, message, line);
+        Ok(())
+    }
+
+    /// Lowers without debug checks, for expressions that must stay on a single line.
+    fn unchecked<T>(&mut self, lower: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        let checking = std::mem::replace(&mut self.checking, false);
+        let result = lower(self);
+        self.checking = checking;
+        result
+    }
+
+    /// Stores the arguments into the callee's parameters and runs its program, leaving any return value in `Ans`.
+    fn invoke(&mut self, name: &str, args: &[Expression], line: usize) -> Result<(), String> {
+        let functions = self.functions;
+        let function = match functions.iter().find(|function| function.name == name) {
+            Some(function) => function,
+            None => return Err(format!("unknown function '{}' on line {}", name, line))
+        };
+        let args = self.in_order(&function.parameters, args)?;
+        let args = arguments(name, &function.parameters, &args, line)?;
+        let mut stores = Vec::new();
+        for (arg, parameter) in args.iter().zip(&function.parameters) {
+            let key = scopes::local(name, &parameter.name);
+            if !matches!(arg.type_.as_ref(), ExpressionType::Lambda(_, _)) {
+                let actual = self.type_of(arg)?;
+                if widen(&parameter.typetype, &actual).as_ref() != Some(&parameter.typetype) {
+                    return Err(format!("parameter '{}' of '{}' expects {:?}, but found {:?} on line {}", parameter.name, name, parameter.typetype, actual, line));
+                }
+                // lists of records are copied list by list, before the other arguments could overwrite them
+                if let Type::RecordList(_) = actual {
+                    self.assign_records(&key, &actual, arg)?;
+                    continue;
+                }
+            }
+            let code = self.callable(arg, &parameter.typetype)?;
+            let variable = self.declare(&key, &parameter.typetype)?;
+            let mut reads = Vec::new();
+            self.reads(arg, &mut reads);
+            stores.push((code, variable, reads, parameter.typetype.clone()));
+        }
+        self.store_arguments(stores)?;
+        self.lines.push(format!("prgm{}", self.programs[name]));
+        Ok(())
+    }
+
+    /// Evaluates the arguments of a call into temporaries in the order they are written, when keywords bind them to
+    /// the parameters in another order and evaluating them could have side effects or fail.
+    fn in_order(&mut self, parameters: &[Parameter], args: &[Expression]) -> Result<Vec<Expression>, String> {
+        let positions: Option<Vec<usize>> = args.iter().enumerate().map(|(i, arg)| match arg.type_.as_ref() {
+            ExpressionType::Keyword(name, _) => parameters.iter().position(|parameter| parameter.name == *name),
+            _ => Some(i),
+        }).collect();
+        // an unknown keyword is reported when the arguments are matched
+        if positions.is_none_or(|positions| positions.is_sorted()) {
+            return Ok(args.to_vec());
+        }
+        args.iter().map(|arg| {
+            let value = match arg.type_.as_ref() {
+                ExpressionType::Keyword(_, value) => value,
+                _ => arg,
+            };
+            if self.is_safe(value) && !self.varies(value) {
+                return Ok(arg.clone());
+            }
+            let stored = self.stored(value)?;
+            Ok(match arg.type_.as_ref() {
+                ExpressionType::Keyword(name, _) => Expression::new(ExpressionType::Keyword(name.clone(), stored), arg.line_number),
+                _ => stored,
+            })
+        }).collect()
+    }
+
+    /// Stores each evaluated argument in the variable its program reads it from. A later argument reading a variable
+    /// that an earlier store overwrites is evaluated into a temporary first.
+    fn store_arguments(&mut self, mut stores: Vec<(Code, String, Vec<String>, Type)>) -> Result<(), String> {
+        for i in 0..stores.len() {
+            if stores[..i].iter().any(|(_, variable, _, _)| stores[i].2.contains(variable)) {
+                let temp = self.temp(&stores[i].3)?;
+                self.lines.push(format!("{}→{}", stores[i].0.text, temp));
+                stores[i].0 = Code::atom(temp);
+            }
+        }
+        for (code, variable, _, _) in stores {
+            if code.text != variable {
+                self.lines.push(format!("{}→{}", code.text, variable));
+            }
+        }
+        Ok(())
+    }
+
+    fn type_of(&self, expression: &Expression) -> Result<Type, String> {
+        let line = expression.line_number;
+        Ok(match expression.type_.as_ref() {
+            ExpressionType::IntLiteral(_) => Type::Int,
+            ExpressionType::BoolLiteral(_) => Type::Bool,
+            ExpressionType::FloatLiteral(_) => Type::Float,
+            ExpressionType::ImaginaryLiteral(_) => Type::Complex,
+            ExpressionType::StringLiteral(_) | ExpressionType::CharLiteral(_) => Type::String,
+            ExpressionType::Field(name) if !self.variables.contains_key(&self.key(name)) && self.programs.contains_key(name) => self.signature(name, line)?,
+            ExpressionType::Field(name) => self.lookup(name, line)?.type_.clone(),
+            ExpressionType::Lambda(_, _) => return Err(format!("a lambda can only be passed or assigned where a Callable is expected on line {}", line)),
+            ExpressionType::ListLiteral(items) if matches!(items.first().map(|item| self.type_of(item)).transpose()?, Some(Type::Record(_))) => {
+                let element = self.type_of(&items[0])?;
+                if let Some(item) = items.iter().find(|item| self.type_of(item).ok().as_ref() != Some(&element)) {
+                    return Err(format!("a list of records cannot also hold {:?} on line {}", self.type_of(item)?, line));
+                }
+                let Type::Record(record) = element else { unreachable!() };
+                Type::RecordList(record)
+            }
+            ExpressionType::ListLiteral(items) => {
+                let mut list = Type::IntList;
+                for item in items {
+                    let element = self.type_of(item)?;
+                    list = match widen(&element_type(&list).unwrap(), &element).as_ref().and_then(list_type) {
+                        Some(list) => list,
+                        None => return Err(format!("lists can only hold numbers, but found {:?} on line {}", element, line))
+                    };
+                }
+                list
+            }
+            ExpressionType::FormatString(_) => Type::String,
+            ExpressionType::Tuple(items) => Type::Tuple(items.iter().map(|item| self.type_of(item)).collect::<Result<Vec<Type>, String>>()?),
+            ExpressionType::Slice(value, _, _, _) => {
+                let type_ = self.type_of(value)?;
+                if type_ != Type::String && storage(&type_) != Storage::List || matches!(type_, Type::Record(_)) {
+                    return Err(format!("cannot slice {:?} on line {}", type_, line));
+                }
+                type_
+            }
+            ExpressionType::Comprehension(element, _, _) => {
+                let element = self.type_of(element)?;
+                match list_type(&element) {
+                    Some(list) => list,
+                    None => return Err(format!("lists can only hold numbers, but found {:?} on line {}", element, line))
+                }
+            }
+            ExpressionType::Call(name, _) if self.is_callable(name) => match &self.lookup(name, line)?.type_ {
+                Type::Callable(_, Some(type_)) => type_.as_ref().clone(),
+                _ => return Err(format!("'{}' does not return a value on line {}", name, line)),
+            },
+            ExpressionType::Call(name, args) => match self.functions.iter().find(|function| function.name == *name) {
+                Some(Function { return_type: Some(type_), .. }) => type_.clone(),
+                Some(_) => return Err(format!("function '{}' does not return a value on line {}", name, line)),
+                None => match self.record(name) {
+                    Some(record) => self.construction_type(record, args, line)?,
+                    None => self.builtin_type(name, args, line)?,
+                },
+            },
+            ExpressionType::Method(receiver, name, args) => self.method_type(receiver, name, args, line)?,
+            ExpressionType::Attribute(receiver, name) => self.attribute_type(receiver, name, line)?,
+            ExpressionType::Operation(operation) if self.is_matrix_operation(operation)? => {
+                self.shape(expression)?;
+                Type::Matrix
+            }
+            ExpressionType::Operation(operation) => match operation {
+                Operation::Add(l, r) => {
+                    let (l, r) = (self.type_of(l)?, self.type_of(r)?);
+                    if l == Type::String && r == Type::String {
+                        Type::String
+                    } else {
+                        arithmetic(&l, &r, operation, line)?
+                    }
+                }
+                Operation::Sub(l, r) | Operation::Mul(l, r) => {
+                    arithmetic(&self.type_of(l)?, &self.type_of(r)?, operation, line)?
+                }
+                Operation::FloorDiv(l, r) | Operation::Mod(l, r) => {
+                    let type_ = arithmetic(&self.type_of(l)?, &self.type_of(r)?, operation, line)?;
+                    if matches!(type_, Type::Complex | Type::ComplexList) {
+                        return Err(format!("cannot use '{}' on complex numbers on line {}", operation, line));
+                    }
+                    type_
+                }
+                Operation::Pow(l, r) => {
+                    let type_ = arithmetic(&self.type_of(l)?, &self.type_of(r)?, operation, line)?;
+                    // a negative integer exponent produces a float in Python
+                    match r.type_.as_ref() {
+                        ExpressionType::IntLiteral(i) if *i >= 0 => type_,
+                        _ => widen(&type_, &Type::Float).unwrap_or(type_),
+                    }
+                }
+                Operation::LBS(l, r) | Operation::RBS(l, r) | Operation::BinaryAnd(l, r) |
+                Operation::BinaryOr(l, r) | Operation::BinaryXor(l, r) => {
+                    let (l, r) = (self.type_of(l)?, self.type_of(r)?);
+                    if !matches!(l, Type::Int | Type::Bool) || !matches!(r, Type::Int | Type::Bool) {
+                        return Err(format!("unsupported operand types for '{}': {:?} and {:?} on line {}", operation, l, r, line));
+                    }
+                    let logical = matches!(operation, Operation::BinaryAnd(_, _) | Operation::BinaryOr(_, _) | Operation::BinaryXor(_, _));
+                    if logical && l == Type::Bool && r == Type::Bool { Type::Bool } else { Type::Int }
+                }
+                Operation::BinaryNot(e) => {
+                    let type_ = self.type_of(e)?;
+                    if !matches!(type_, Type::Int | Type::Bool) {
+                        return Err(format!("bad operand type for '~': {:?} on line {}", type_, line));
+                    }
+                    Type::Int
+                }
+                Operation::Div(l, r) => {
+                    let type_ = arithmetic(&self.type_of(l)?, &self.type_of(r)?, operation, line)?;
+                    widen(&type_, &Type::Float).unwrap_or(type_)
+                }
+                Operation::GreaterThan(l, r) | Operation::LessThan(l, r) |
+                Operation::GreaterEquals(l, r) | Operation::LessEquals(l, r) => {
+                    let (l, r) = (self.type_of(l)?, self.type_of(r)?);
+                    if l == Type::String || r == Type::String {
+                        return Err(format!("strings can only be compared with '==' and '!=' on line {}", line));
+                    }
+                    if l == Type::Complex || r == Type::Complex {
+                        return Err(format!("complex numbers can only be compared with '==' and '!=' on line {}", line));
+                    }
+                    Type::Bool
+                }
+                Operation::In(needle, haystack) => {
+                    let (needle, haystack) = (self.type_of(needle)?, self.type_of(haystack)?);
+                    let searchable = match (&needle, &haystack) {
+                        (Type::String, Type::String) => true,
+                        (_, Type::IntList | Type::FloatList | Type::ComplexList) => storage(&needle) == Storage::Real,
+                        _ => false,
+                    };
+                    if !searchable {
+                        return Err(format!("membership tests of {:?} in {:?} are not supported on line {}", needle, haystack, line));
+                    }
+                    Type::Bool
+                }
+                // TI-Basic's and/or yield 0 or 1 rather than one of their operands
+                Operation::Equals(_, _) | Operation::NotEqual(_, _) |
+                Operation::And(_, _) | Operation::Or(_, _) | Operation::Not(_) => Type::Bool,
+                Operation::Negative(e) | Operation::Increment(e) | Operation::Decrement(e) => match self.type_of(e)? {
+                    Type::Bool => Type::Int,
+                    type_ => type_,
+                },
+                Operation::ArrayIndex(list, _) => {
+                    // matrices only hold reals
+                    if let ExpressionType::Operation(Operation::ArrayIndex(matrix, _)) = list.type_.as_ref() {
+                        if self.type_of(matrix)? == Type::Matrix {
+                            return Ok(Type::Float);
+                        }
+                    }
+                    let type_ = self.type_of(list)?;
+                    match (element_type(&type_), type_) {
+                        (Some(element), _) => element,
+                        (None, Type::RecordList(record)) => Type::Record(record),
+                        (None, Type::String) => Type::String,
+                        (None, Type::Matrix) => return Err(format!("a matrix row must be indexed again, as in m[r][c], on line {}", line)),
+                        (None, type_) => return Err(format!("cannot index value of type {:?} on line {}", type_, line))
+                    }
+                }
+                o => return Err(format!("operator '{}' is not supported on line {}", o, line)),
+            },
+            ExpressionType::Comparison(_, _) => Type::Bool,
+            ExpressionType::Conditional(_, value, other) => {
+                let (value, other) = (self.type_of(value)?, self.type_of(other)?);
+                match widen(&value, &other) {
+                    Some(type_) => type_,
+                    None => return Err(format!("conditional expression yields both {:?} and {:?} on line {}", value, other, line))
+                }
+            }
+            ExpressionType::Keyword(name, _) => return Err(format!("unexpected keyword argument '{}' on line {}", name, line)),
+            ExpressionType::Empty => return Err(format!("expected expression on line {}", line)),
+        })
+    }
+
+    /// Whether evaluating `expression` twice could give two different values, so its result must be stored to be reused.
+    fn varies(&self, expression: &Expression) -> bool {
+        let volatile = matches!(expression.type_.as_ref(), ExpressionType::Call(name, _) if !self.programs.contains_key(name) && builtins::is_volatile(name));
+        volatile || children(expression).into_iter().any(|child| self.varies(child))
+    }
+
+    /// Whether evaluating `expression` can neither error nor have side effects on the calculator.
+    fn is_safe(&self, expression: &Expression) -> bool {
+        match expression.type_.as_ref() {
+            ExpressionType::Call(name, args) => {
+                !self.programs.contains_key(name) && builtins::is_pure(name) && args.iter().all(|arg| self.is_safe(arg))
+            }
+            ExpressionType::Method(_, _, _) | ExpressionType::Comprehension(_, _, _) | ExpressionType::Slice(_, _, _, _) => false,
+            ExpressionType::Attribute(receiver, _) => self.is_safe(receiver),
+            ExpressionType::FormatString(parts) => parts.iter().all(|part| self.is_safe(part) && self.type_of(part).is_ok_and(|type_| type_ == Type::String)),
+            ExpressionType::ListLiteral(items) | ExpressionType::Tuple(items) | ExpressionType::Comparison(items, _) => items.iter().all(|item| self.is_safe(item)),
+            ExpressionType::Conditional(condition, value, other) => self.is_safe(condition) && self.is_safe(value) && self.is_safe(other),
+            ExpressionType::Operation(operation) => match operation {
+                Operation::ArrayIndex(_, _) | Operation::Increment(_) | Operation::Decrement(_) | Operation::MatMul(_, _) |
+                Operation::BinaryAnd(_, _) | Operation::BinaryOr(_, _) | Operation::BinaryXor(_, _) => false,
+                Operation::Pow(l, r) => self.is_safe(l) && matches!(r.type_.as_ref(), ExpressionType::IntLiteral(_)),
+                Operation::Div(l, r) | Operation::FloorDiv(l, r) | Operation::Mod(l, r) => self.is_safe(l) && match r.type_.as_ref() {
+                    ExpressionType::IntLiteral(i) => *i != 0,
+                    ExpressionType::FloatLiteral(f) | ExpressionType::ImaginaryLiteral(f) => *f != 0.0,
+                    _ => false,
+                },
+                Operation::Not(e) | Operation::Negative(e) | Operation::Optional(e) | Operation::BinaryNot(e) => self.is_safe(e),
+                Operation::Construction(items) => items.iter().all(|item| self.is_safe(item)),
+                Operation::Add(l, r) | Operation::Sub(l, r) | Operation::Mul(l, r) | Operation::Equals(l, r) |
+                Operation::GreaterThan(l, r) | Operation::LessThan(l, r) | Operation::GreaterEquals(l, r) |
+                Operation::LessEquals(l, r) | Operation::LBS(l, r) | Operation::RBS(l, r) |
+                Operation::And(l, r) | Operation::Or(l, r) | Operation::NotEqual(l, r) | Operation::In(l, r) => self.is_safe(l) && self.is_safe(r),
+            },
+            _ => true,
+        }
+    }
+
+    /// Collects the TI-Basic variables read while evaluating `expression`.
+    fn reads(&self, expression: &Expression, names: &mut Vec<String>) {
+        if let ExpressionType::Field(name) = expression.type_.as_ref() {
+            if let Some(variable) = self.variables.get(&self.key(name)) {
+                names.push(variable.name.clone());
+            }
+        }
+        children(expression).into_iter().for_each(|child| self.reads(child, names));
+    }
+
+    fn current(&self) -> &'a Function {
+        self.functions.iter().find(|function| function.name == self.function).unwrap()
+    }
+
+    fn lookup(&self, name: &str, line: usize) -> Result<&Variable, String> {
+        match self.variables.get(&self.key(name)) {
+            Some(variable) => Ok(variable),
+            None => Err(format!("unknown variable '{}' on line {}", name, line))
+        }
+    }
+
+    /// Returns the TI-Basic variable backing `name`, allocating one if this is its first use.
+    fn declare(&mut self, name: &str, type_: &Type) -> Result<String, String> {
+        if matches!(type_, Type::Complex | Type::ComplexList) {
+            self.complex = true;
+        }
+        let key = self.key(name);
+        if let Some(variable) = self.variables.get_mut(&key) {
+            return match widen(&variable.type_, type_) {
+                Some(widened) if storage(&widened) == storage(&variable.type_) => {
+                    variable.type_ = widened;
+                    Ok(variable.name.clone())
+                }
+                _ => Err(format!("variable '{}' was {:?}, but is used as {:?}", name, variable.type_, type_))
+            };
+        }
+        if let (Type::RecordList(record), false) = (type_, self.pins.contains_key(&key)) {
+            return self.declare_records(name, record);
+        }
+        let taken: Vec<&str> = self.variables.iter()
+            .filter(|(other, _)| !self.scopes.can_share(&key, other))
+            .map(|(_, variable)| variable.name.as_str())
+            .chain(self.pins.values().map(String::as_str))
+            .collect();
+        let allocated = match (self.pins.get(&key), storage(type_)) {
+            (Some(pinned), storage) if register_storage(pinned).as_ref() != Some(&storage) => {
+                return Err(format!("'{}' is {:?}, which cannot be held in {}", name, type_, pinned));
+            }
+            (Some(pinned), _) => Some(pinned.clone()),
+            (None, Storage::Real) => REALS.iter().find(|real| !taken.contains(real)).map(|real| real.to_string()),
+            (None, Storage::String) => STRINGS.iter().find(|string| !taken.contains(string)).map(|string| string.to_string()),
+            (None, Storage::Records) => unreachable!(),
+            (None, Storage::Tuple) => return Err(format!("'{}' cannot hold a tuple, unpack it into one variable per value", name)),
+            (None, Storage::Matrix) => MATRICES.iter().find(|matrix| !taken.contains(matrix)).map(|matrix| matrix.to_string()),
+            (None, Storage::List) => {
+                // the lists of a list of records are named after their fields
+                let field = key.rsplit(['.', ':']).next().unwrap();
+                let base: String = field.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).take(5).collect();
+                let base = if base.starts_with(|c: char| c.is_ascii_alphabetic()) { base } else { format!("L{}", base) };
+                (0..100).map(|i| {
+                    if i == 0 { format!("ʟ{}", base) } else {
+                        let suffix = i.to_string();
+                        format!("ʟ{}{}", &base[..base.len().min(5 - suffix.len())], suffix)
+                    }
+                }).find(|list| !taken.contains(&list.as_str()))
+            }
+        };
+        match allocated {
+            Some(allocated) => {
+                self.variables.insert(key, Variable { name: allocated.clone(), type_: type_.clone() });
+                Ok(allocated)
+            }
+            None => Err(format!("ran out of TI-Basic variables for {:?} while allocating '{}' in '{}': the globals and the locals of every function that can run at the same time all need their own", type_, name, self.function))
+        }
+    }
+
+    fn temp(&mut self, type_: &Type) -> Result<String, String> {
+        let key = self.temp_key(type_);
+        self.declare(&key, type_)
+    }
+
+    /// The key of a new temporary, for expressions that read it back as a variable.
+    fn temp_key(&mut self, type_: &Type) -> String {
+        self.temps += 1;
+        format!("{}$temp{}{:?}", self.function, self.temps, storage(type_))
+    }
+
+    /// Evaluates `expression` into a new temporary, returning an expression that reads it.
+    fn stored(&mut self, expression: &Expression) -> Result<Expression, String> {
+        let type_ = self.type_of(expression)?;
+        let code = self.value(expression)?;
+        let key = self.temp_key(&type_);
+        let temp = self.declare(&key, &type_)?;
+        self.lines.push(format!("{}→{}", code.text, temp));
+        Ok(Expression::new(ExpressionType::Field(key), expression.line_number))
+    }
+
+    /// Registers the helper program `name`, generating its body on first use, and returns the line calling it.
+    fn helper(&mut self, name: &str, body: impl FnOnce(&mut Self) -> Result<Vec<String>, String>) -> Result<String, String> {
+        if self.programs.values().any(|program| program == name) {
+            return Err(format!("prgm{} is reserved for one of Crunch's helper programs, rename the function compiling to it", name));
+        }
+        if !self.helpers.iter().any(|helper| helper.name == name) {
+            let lines = body(self)?;
+            self.helpers.push(Program { name: name.to_string(), lines });
+        }
+        Ok(format!("prgm{}", name))
+    }
+
+    fn capture<T>(&mut self, lower: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<(T, Vec<String>), String> {
+        let outer = std::mem::take(&mut self.lines);
+        let result = lower(self);
+        let inner = std::mem::replace(&mut self.lines, outer);
+        Ok((result?, inner))
+    }
+}
+
+fn program_name(function: &str) -> Result<String, String> {
+    let name: String = function.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).take(8).collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(format!("cannot derive a TI-Basic program name from function '{}'", function));
+    }
+    Ok(name)
+}
+
+/// The kind of value a TI-Basic variable named by the user can hold.
+fn register_storage(register: &str) -> Option<Storage> {
+    if REALS.contains(&register) {
+        Some(Storage::Real)
+    } else if STRINGS.contains(&register) {
+        Some(Storage::String)
+    } else if MATRICES.contains(&register) {
+        Some(Storage::Matrix)
+    } else {
+        let name = register.strip_prefix('ʟ')?;
+        let valid = (1..=5).contains(&name.len())
+            && name.starts_with(|c: char| c.is_ascii_uppercase())
+            && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        valid.then_some(Storage::List)
+    }
+}
+
+/// Defines a function listing the expressions evaluated directly by a statement, leaving out those of nested
+/// statements, once for shared and once for mutable borrows.
+macro_rules! expressions {
+    ($name:ident, $as_ref:ident, $iter:ident $(, $mutability:tt)?) => {
+        fn $name(statement: &$($mutability)? Statement) -> Vec<&$($mutability)? Expression> {
+            match statement.type_.$as_ref() {
+                StatementType::Assignment(target, value) | StatementType::Declaration(target, _, value) => vec![target, value],
+                StatementType::If(condition, _, _) | StatementType::While(condition, _) => vec![condition],
+                StatementType::Return(Some(value)) | StatementType::Ignored(value) | StatementType::Del(value) => vec![value],
+                StatementType::Assert(condition, message) => std::iter::once(condition).chain(message).collect(),
+                StatementType::Match(subject, cases) => std::iter::once(subject).chain(cases.$iter().flat_map(|(patterns, _)| patterns)).collect(),
+                StatementType::Return(None) | StatementType::For(_, _, _) | StatementType::Comment(_) | StatementType::Pass |
+                StatementType::Break | StatementType::Continue | StatementType::Global(_) | StatementType::Try(_, _, _, _) => Vec::new(),
+            }
+        }
+    };
+}
+
+expressions!(expressions, as_ref, iter);
+expressions!(expressions_mut, as_mut, iter_mut, mut);
+
+/// The blocks nested inside `statement`.
+fn nested(statement: &Statement) -> Vec<&[Statement]> {
+    match statement.type_.as_ref() {
+        StatementType::If(_, body, else_body) => std::iter::once(body.as_slice()).chain(else_body.as_ref().map(std::slice::from_ref)).collect(),
+        StatementType::While(_, body) | StatementType::For(_, _, body) => vec![body],
+        StatementType::Match(_, cases) => cases.iter().map(|(_, body)| body.as_slice()).collect(),
+        StatementType::Try(body, handlers, otherwise, finally) => {
+            [body, otherwise, finally].into_iter().map(Vec::as_slice).chain(handlers.iter().map(|(_, handler)| handler.as_slice())).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Defines a function listing the expressions directly nested inside an expression, once for shared and once for
+/// mutable borrows, so every traversal visits the same children. The body of a lambda is left out, as it is
+/// evaluated when the lambda is called rather than where it is written.
+macro_rules! children {
+    ($visibility:vis $name:ident, $as_ref:ident, $iter:ident $(, $mutability:tt)?) => {
+        $visibility fn $name(expression: &$($mutability)? Expression) -> Vec<&$($mutability)? Expression> {
+            match expression.type_.$as_ref() {
+                ExpressionType::Call(_, args) | ExpressionType::ListLiteral(args) | ExpressionType::Tuple(args) |
+                ExpressionType::Comparison(args, _) | ExpressionType::FormatString(args) => args.$iter().collect(),
+                ExpressionType::Method(receiver, _, args) => std::iter::once(receiver).chain(args).collect(),
+                ExpressionType::Attribute(receiver, _) | ExpressionType::Keyword(_, receiver) => vec![receiver],
+                ExpressionType::Conditional(condition, value, other) => vec![condition, value, other],
+                ExpressionType::Comprehension(element, _, iterable) => vec![element, iterable],
+                ExpressionType::Slice(value, start, stop, step) => std::iter::once(value).chain(start).chain(stop).chain(step).collect(),
+                ExpressionType::Operation(operation) => match operation {
+                    Operation::Construction(items) => items.$iter().collect(),
+                    Operation::Not(e) | Operation::Negative(e) | Operation::Optional(e) | Operation::BinaryNot(e) |
+                    Operation::Increment(e) | Operation::Decrement(e) => vec![e],
+                    Operation::Add(l, r) | Operation::Sub(l, r) | Operation::Mul(l, r) | Operation::Div(l, r) |
+                    Operation::FloorDiv(l, r) | Operation::Mod(l, r) | Operation::Pow(l, r) | Operation::Equals(l, r) | Operation::GreaterThan(l, r) |
+                    Operation::LessThan(l, r) | Operation::GreaterEquals(l, r) | Operation::LessEquals(l, r) |
+                    Operation::LBS(l, r) | Operation::RBS(l, r) | Operation::ArrayIndex(l, r) |
+                    Operation::BinaryAnd(l, r) | Operation::BinaryOr(l, r) | Operation::BinaryXor(l, r) |
+                    Operation::And(l, r) | Operation::Or(l, r) | Operation::NotEqual(l, r) | Operation::In(l, r) |
+                    Operation::MatMul(l, r) => vec![l, r],
+                },
+                _ => Vec::new()
+            }
+        }
+    };
+}
+
+children!(children, as_ref, iter);
+children!(pub(crate) children_mut, as_mut, iter_mut, mut);
+
+/// Matches the positional and keyword arguments of a call to `callee` with its parameters, filling in defaults.
+fn arguments(callee: &str, parameters: &[Parameter], args: &[Expression], line: usize) -> Result<Vec<Expression>, String> {
+    let mut resolved: Vec<Option<Expression>> = vec![None; parameters.len()];
+    for (i, arg) in args.iter().enumerate() {
+        let position = match arg.type_.as_ref() {
+            ExpressionType::Keyword(name, _) => match parameters.iter().position(|parameter| parameter.name == *name) {
+                Some(position) => position,
+                None => return Err(format!("'{}' got an unexpected keyword argument '{}' on line {}", callee, name, line))
+            },
+            _ if i >= parameters.len() => {
+                return Err(format!("'{}' takes {} arguments, but {} were given on line {}", callee, parameters.len(), args.len(), line));
+            }
+            _ => i,
+        };
+        if resolved[position].is_some() {
+            return Err(format!("'{}' got multiple values for '{}' on line {}", callee, parameters[position].name, line));
+        }
+        resolved[position] = Some(match arg.type_.as_ref() {
+            ExpressionType::Keyword(_, value) => value.clone(),
+            _ => arg.clone(),
+        });
+    }
+    resolved.into_iter().zip(parameters).map(|(arg, parameter)| match arg.or_else(|| parameter.default.clone()) {
+        Some(arg) => Ok(arg),
+        None => Err(format!("'{}' is missing the argument '{}' on line {}", callee, parameter.name, line))
+    }).collect()
+}
+
+/// Whether `expression` is a literal, which evaluates the same wherever it is written.
+fn is_constant(expression: &Expression) -> bool {
+    match expression.type_.as_ref() {
+        ExpressionType::IntLiteral(_) | ExpressionType::FloatLiteral(_) | ExpressionType::ImaginaryLiteral(_) |
+        ExpressionType::StringLiteral(_) | ExpressionType::CharLiteral(_) | ExpressionType::BoolLiteral(_) => true,
+        ExpressionType::Operation(Operation::Negative(inner)) => is_constant(inner),
+        ExpressionType::ListLiteral(items) => items.iter().all(is_constant),
+        _ => false
+    }
+}
+
+/// The value of a numeric `expression` at compile time, given the values `known` for some variables. Truth values
+/// are 0 or 1, as in TI-Basic.
+fn evaluate(expression: &Expression, known: &HashMap<String, f64>) -> Option<f64> {
+    let truth = |value: bool| if value { 1.0 } else { 0.0 };
+    let both = |l: &Expression, r: &Expression| Some((evaluate(l, known)?, evaluate(r, known)?));
+    Some(match expression.type_.as_ref() {
+        ExpressionType::IntLiteral(i) => *i as f64,
+        ExpressionType::FloatLiteral(f) => *f,
+        ExpressionType::BoolLiteral(b) => truth(*b),
+        ExpressionType::Field(name) => *known.get(name)?,
+        ExpressionType::Operation(operation) => match operation {
+            Operation::Negative(e) => -evaluate(e, known)?,
+            Operation::Not(e) => truth(evaluate(e, known)? == 0.0),
+            Operation::Add(l, r) => { let (l, r) = both(l, r)?; l + r }
+            Operation::Sub(l, r) => { let (l, r) = both(l, r)?; l - r }
+            Operation::Mul(l, r) => { let (l, r) = both(l, r)?; l * r }
+            Operation::Equals(l, r) => { let (l, r) = both(l, r)?; truth(l == r) }
+            Operation::NotEqual(l, r) => { let (l, r) = both(l, r)?; truth(l != r) }
+            Operation::LessThan(l, r) => { let (l, r) = both(l, r)?; truth(l < r) }
+            Operation::GreaterThan(l, r) => { let (l, r) = both(l, r)?; truth(l > r) }
+            Operation::LessEquals(l, r) => { let (l, r) = both(l, r)?; truth(l <= r) }
+            Operation::GreaterEquals(l, r) => { let (l, r) = both(l, r)?; truth(l >= r) }
+            Operation::And(l, r) => { let (l, r) = both(l, r)?; truth(l != 0.0 && r != 0.0) }
+            Operation::Or(l, r) => { let (l, r) = both(l, r)?; truth(l != 0.0 || r != 0.0) }
+            _ => return None
+        },
+        _ => return None
+    })
+}
+
+/// The opposite of `condition`, flipping a comparison rather than wrapping it in `not(`.
+fn negation(condition: &Expression) -> Operation {
+    match condition.type_.as_ref() {
+        ExpressionType::Operation(operation) => match operation.clone() {
+            Operation::Equals(l, r) => Operation::NotEqual(l, r),
+            Operation::NotEqual(l, r) => Operation::Equals(l, r),
+            Operation::LessThan(l, r) => Operation::GreaterEquals(l, r),
+            Operation::GreaterThan(l, r) => Operation::LessEquals(l, r),
+            Operation::LessEquals(l, r) => Operation::GreaterThan(l, r),
+            Operation::GreaterEquals(l, r) => Operation::LessThan(l, r),
+            _ => Operation::Not(condition.clone()),
+        },
+        _ => Operation::Not(condition.clone()),
+    }
+}
+
+/// Whether `expression` is an integer that can never be negative, so it needs no counting back as an index.
+fn is_natural(expression: &Expression) -> bool {
+    match expression.type_.as_ref() {
+        ExpressionType::IntLiteral(i) => *i >= 0,
+        ExpressionType::Call(name, _) => name == "len",
+        ExpressionType::Operation(Operation::Add(l, r) | Operation::Mul(l, r)) => is_natural(l) && is_natural(r),
+        ExpressionType::Operation(Operation::Mod(_, r)) => integer_literal(r).is_some_and(|r| r > 0),
+        _ => false,
+    }
+}
+
+/// The arguments of `iterable` when it is a call to `range()`.
+fn range(iterable: &Expression) -> Option<&[Expression]> {
+    match iterable.type_.as_ref() {
+        ExpressionType::Call(name, args) if name == "range" => Some(args),
+        _ => None
+    }
+}
+
+fn offset(code: Code, delta: i64) -> Code {
+    match delta {
+        0 => code,
+        _ => Code::new(format!("{}{}{}", code.wrap(ADDITIVE), if delta < 0 { "-" } else { "+" }, delta.abs()), ADDITIVE),
+    }
+}
+
+/// Rejects a slice whose constant bounds always leave it empty, as TI-Basic has no empty lists or strings to give,
+/// or that run past the `known` length of a literal.
+fn check_slice(start: Option<Option<i64>>, stop: Option<Option<i64>>, step: i64, known: Option<i64>, line: usize) -> Result<(), String> {
+    if let Some(length) = known {
+        if let Some(bound) = [start, stop].into_iter().flatten().flatten().find(|bound| *bound < -length || *bound > length) {
+            return Err(format!("the slice bound {} is out of range for a length of {} on line {}", bound, length, line));
+        }
+    }
+    // each bound as an offset from the start, or from the end when it counts back from there
+    let position = |bound: i64| match known {
+        Some(length) if bound < 0 => (bound + length, false),
+        _ => (bound, bound < 0),
+    };
+    let (start, stop) = match (step > 0, start, stop) {
+        (_, Some(None), _) | (_, _, Some(None)) => return Ok(()),
+        (true, start, stop) => (position(start.flatten().unwrap_or(0)), match stop {
+            Some(stop) => position(stop.unwrap()),
+            None => known.map_or((0, true), |length| (length, false)),
+        }),
+        // a negative step stops before the first element unless told otherwise
+        (false, _, None) => return Ok(()),
+        (false, start, Some(stop)) => (match start {
+            Some(start) => position(start.unwrap()),
+            None => known.map_or((-1, true), |length| (length - 1, false)),
+        }, position(stop.unwrap())),
+    };
+    let empty = start.1 == stop.1 && if step > 0 { start.0 >= stop.0 } else { start.0 <= stop.0 };
+    if empty {
+        return Err(format!("the slice is always empty, which TI-Basic cannot hold, on line {}", line));
+    }
+    Ok(())
+}
+
+/// An integer literal written the way the parser writes `-1`.
+fn literal(value: i64, line: usize) -> Expression {
+    let literal = Expression::new(ExpressionType::IntLiteral(value.abs()), line);
+    if value < 0 {
+        Expression::new(ExpressionType::Operation(Operation::Negative(literal)), line)
+    } else {
+        literal
+    }
+}
+
+/// The value of an integer literal, including negated ones like `-1`.
+fn integer_literal(expression: &Expression) -> Option<i64> {
+    match expression.type_.as_ref() {
+        ExpressionType::IntLiteral(i) => Some(*i),
+        ExpressionType::Operation(Operation::Negative(inner)) => integer_literal(inner).map(|i| -i),
+        _ => None
+    }
+}
+
+fn list_type(element: &Type) -> Option<Type> {
+    match element {
+        Type::Bool | Type::Int => Some(Type::IntList),
+        Type::Float => Some(Type::FloatList),
+        Type::Complex => Some(Type::ComplexList),
+        _ => None
+    }
+}
+
+fn storage(type_: &Type) -> Storage {
+    match type_ {
+        Type::Bool | Type::Int | Type::Float | Type::Complex | Type::Callable(_, _) => Storage::Real,
+        Type::String => Storage::String,
+        Type::IntList | Type::FloatList | Type::ComplexList | Type::Record(_) => Storage::List,
+        Type::Matrix => Storage::Matrix,
+        Type::RecordList(_) => Storage::Records,
+        Type::Tuple(_) => Storage::Tuple,
+    }
+}
+
+fn element_type(type_: &Type) -> Option<Type> {
+    match type_ {
+        Type::IntList => Some(Type::Int),
+        Type::FloatList => Some(Type::Float),
+        Type::ComplexList => Some(Type::Complex),
+        _ => None
+    }
+}
+
+/// The narrowest type both `a` and `b` can be stored as, if any.
+fn widen(a: &Type, b: &Type) -> Option<Type> {
+    fn rank(type_: &Type) -> Option<(u8, bool)> {
+        match type_ {
+            Type::Bool => Some((0, false)),
+            Type::Int => Some((1, false)),
+            Type::Float => Some((2, false)),
+            Type::Complex => Some((3, false)),
+            Type::IntList => Some((1, true)),
+            Type::FloatList => Some((2, true)),
+            Type::ComplexList => Some((3, true)),
+            _ => None
+        }
+    }
+    if a == b {
+        return Some(a.clone());
+    }
+    let ((a, a_list), (b, b_list)) = (rank(a)?, rank(b)?);
+    if a_list != b_list {
+        return None;
+    }
+    Some(match (a.max(b), a_list) {
+        (0, false) => Type::Bool,
+        (1, false) => Type::Int,
+        (2, false) => Type::Float,
+        (_, false) => Type::Complex,
+        (0 | 1, true) => Type::IntList,
+        (2, true) => Type::FloatList,
+        (_, true) => Type::ComplexList,
+    })
+}
+
+/// Result type of a numeric operator, where lists combine element-wise with lists and scalars.
+fn arithmetic(l: &Type, r: &Type, operation: &Operation, line: usize) -> Result<Type, String> {
+    let scalar = |type_: &Type| element_type(type_).unwrap_or(type_.clone());
+    let type_ = match widen(&scalar(l), &scalar(r)) {
+        // arithmetic on bools counts them as 0 and 1
+        Some(Type::Bool) => Type::Int,
+        Some(type_) if storage(&type_) == Storage::Real => type_,
+        _ => return Err(format!("unsupported operand types for '{}': {:?} and {:?} on line {}", operation, l, r, line))
+    };
+    if storage(l) == Storage::List || storage(r) == Storage::List {
+        Ok(match type_ {
+            Type::Int => Type::IntList,
+            Type::Float => Type::FloatList,
+            _ => Type::ComplexList,
+        })
+    } else {
+        Ok(type_)
+    }
+}
+
+fn string(text: &str, line: usize) -> Result<Code, String> {
+    if let Some(c) = text.chars().find(|c| matches!(c, '"' | '→') || c.is_control()) {
+        return Err(format!("string on line {} contains {:?}, which TI-Basic strings cannot hold", line, c));
+    }
+    Ok(Code::atom(format!("\"{}\"", text)))
+}
+
+fn is_literal(text: &str) -> bool {
+    text.len() >= 2 && text.starts_with('"') && text.ends_with('"') && text.matches('"').count() == 2
+}
+
+fn is_boolean(expression: &Expression) -> bool {
+    match expression.type_.as_ref() {
+        ExpressionType::BoolLiteral(_) | ExpressionType::Comparison(_, _) => true,
+        ExpressionType::Operation(operation) => matches!(operation,
+            Operation::Equals(_, _) | Operation::NotEqual(_, _) | Operation::GreaterThan(_, _) |
+            Operation::LessThan(_, _) | Operation::GreaterEquals(_, _) | Operation::LessEquals(_, _) |
+            Operation::And(_, _) | Operation::Or(_, _) | Operation::Not(_) | Operation::In(_, _)
+        ),
+        _ => false
+    }
+}
+
+/// Python's `str()` of a 0/1 truth value, cut out of one literal.
+fn bool_string(code: &Code) -> Code {
+    Code::atom(format!("sub(\"FalseTrue\",1+5{},5-{})", code.wrap(POSTFIX), code.wrap(ADDITIVE + 1)))
+}
+
+fn is_zero(expression: &Expression) -> bool {
+    match expression.type_.as_ref() {
+        ExpressionType::IntLiteral(i) => *i == 0,
+        ExpressionType::FloatLiteral(f) | ExpressionType::ImaginaryLiteral(f) => *f == 0.0,
+        ExpressionType::BoolLiteral(b) => !b,
+        _ => false
+    }
+}
+
+fn relational(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::Equals => "=",
+        Operator::NotEqual => "≠",
+        Operator::GreaterThan => ">",
+        Operator::LessThan => "<",
+        Operator::GreaterEquals => "≥",
+        Operator::LessEquals => "≤",
+        _ => unreachable!()
+    }
+}
+
+fn integer(value: i64) -> Code {
+    if value < 0 {
+        Code::new(format!("⁻{}", value.unsigned_abs()), NEGATION)
+    } else {
+        Code::atom(value.to_string())
+    }
+}
+
+fn float(value: f64) -> Code {
+    if value.fract() == 0.0 && value.abs() < 1e14 {
+        return integer(value as i64);
+    }
+    let text = if value.abs() >= 1e10 || value.abs() < 1e-4 {
+        format!("{:e}", value.abs()).replace('e', "ᴇ").replace('-', "⁻")
+    } else {
+        format!("{}", value.abs()).trim_start_matches('0').to_string()
+    };
+    if value < 0.0 {
+        Code::new(format!("⁻{}", text), NEGATION)
+    } else {
+        Code::atom(text)
+    }
+}
+
+/// Imaginary literals use the calculator's i, which multiplies implicitly like 4i.
+fn imaginary(value: f64) -> Code {
+    if value == 1.0 {
+        return Code::atom("i".to_string());
+    }
+    Code::new(format!("{}i", float(value).text), MULTIPLICATIVE)
+}
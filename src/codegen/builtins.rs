@@ -0,0 +1,364 @@
+use crate::statements::{Expression, ExpressionType};
+use crate::types::Type;
+use super::{matrices, Code, Generator, Storage, ADDITIVE, MULTIPLICATIVE, POSTFIX};
+
+/// Built-in functions that can neither error nor have side effects on the calculator.
+const PURE: [&str; 11] = ["len", "sum", "min", "max", "abs", "angle", "bool", "Complex", "Matrix", "transpose", "dim"];
+
+/// Built-in functions that can return something else each time they are called, so their result is never reused.
+const VOLATILE: [&str; 1] = ["getKey"];
+
+pub(super) fn is_pure(name: &str) -> bool {
+    PURE.contains(&name)
+}
+
+pub(super) fn is_volatile(name: &str) -> bool {
+    VOLATILE.contains(&name)
+}
+
+pub(super) fn arity(name: &str, args: &[Expression], expected: usize, line: usize) -> Result<(), String> {
+    if args.len() != expected {
+        return Err(format!("{}() takes {} arguments, but {} were given on line {}", name, expected, args.len(), line));
+    }
+    Ok(())
+}
+
+impl Generator<'_> {
+
+    /// Lowers a built-in called for its effect rather than its value.
+    pub(super) fn builtin_statement(&mut self, name: &str, args: &[Expression], line: usize) -> Result<(), String> {
+        match name {
+            "disp" | "print" => {
+                let mut values = Vec::new();
+                for arg in args {
+                    match arg.type_.as_ref() {
+                        // each line of a string literal is displayed on its own row
+                        ExpressionType::StringLiteral(text) => {
+                            for row in text.split('\n') {
+                                values.push(super::string(row, line)?.text);
+                            }
+                        }
+                        _ => values.push(self.expression(arg)?.text),
+                    }
+                }
+                self.lines.push(format!("Disp {}", values.join(",")));
+                Ok(())
+            }
+            _ => {
+                self.builtin_type(name, args, line)?;
+                Err(format!("result of {}() is unused on line {}", name, line))
+            }
+        }
+    }
+
+    pub(super) fn builtin(&mut self, name: &str, args: &[Expression], line: usize) -> Result<Code, String> {
+        self.builtin_type(name, args, line)?;
+        match name {
+            name if matrices::FUNCTIONS.contains(&name) => self.matrix_builtin(name, args),
+            "len" => {
+                let type_ = self.type_of(&args[0])?;
+                let value = match (&type_, args[0].type_.as_ref()) {
+                    // every field's list has the same length
+                    (Type::RecordList(_), ExpressionType::Field(records)) => Code::atom(self.field_lists(records, line)?.swap_remove(0)),
+                    _ => self.expression(&args[0])?,
+                };
+                let function = if type_ == Type::String { "length" } else { "dim" };
+                Ok(Code::atom(format!("{}({})", function, value.text)))
+            }
+            "sum" | "abs" | "angle" => {
+                let value = self.expression(&args[0])?;
+                Ok(Code::atom(format!("{}({})", name, value.text)))
+            }
+            // the calculator writes a complex number as a+bi, multiplying by i implicitly
+            "Complex" => {
+                self.complex = true;
+                let real = self.expression(&args[0])?;
+                let Some(imaginary) = args.get(1) else {
+                    return Ok(real);
+                };
+                let imaginary = match self.expression(imaginary)? {
+                    code if code.text == "1" => "i".to_string(),
+                    code => format!("{}i", code.wrap(MULTIPLICATIVE)),
+                };
+                Ok(match imaginary.strip_prefix('⁻') {
+                    Some(negated) => Code::new(format!("{}-{}", real.wrap(ADDITIVE), negated), ADDITIVE),
+                    None => Code::new(format!("{}+{}", real.wrap(ADDITIVE), imaginary), ADDITIVE),
+                })
+            }
+            // min( and max( take either one list or two numbers, so longer argument lists are nested
+            "min" | "max" => {
+                let mut code = self.expression(&args[0])?;
+                for arg in &args[1..] {
+                    let other = self.expression(arg)?;
+                    code = Code::atom(format!("{}({},{})", name, code.text, other.text));
+                }
+                if args.len() == 1 {
+                    code = Code::atom(format!("{}({})", name, code.text));
+                }
+                Ok(code)
+            }
+            // SortA( only sorts a list variable in place, so the list is copied first
+            "sorted" => {
+                let type_ = self.type_of(&args[0])?;
+                let list = self.expression(&args[0])?;
+                let temp = self.temp(&type_)?;
+                self.lines.push(format!("{}→{}", list.text, temp));
+                self.lines.push(format!("SortA({})", temp));
+                Ok(Code::atom(temp))
+            }
+            "str" => match (args[0].type_.as_ref(), self.type_of(&args[0])?) {
+                (ExpressionType::IntLiteral(i), _) => super::string(&super::integer(*i).text, line),
+                (ExpressionType::BoolLiteral(b), _) => super::string(if *b { "True" } else { "False" }, line),
+                (_, Type::String) => self.expression(&args[0]),
+                (_, Type::Bool) => {
+                    let code = self.expression(&args[0])?;
+                    Ok(super::bool_string(&code))
+                }
+                _ => {
+                    let code = self.expression(&args[0])?;
+                    self.number_string(code)
+                }
+            },
+            "bool" => {
+                let type_ = self.type_of(&args[0])?;
+                let value = self.expression(&args[0])?;
+                let measured = match super::storage(&type_) {
+                    Storage::String => Code::atom(format!("length({})", value.text)),
+                    Storage::List => Code::atom(format!("dim({})", value.text)),
+                    _ if type_ == Type::Bool => return Ok(value),
+                    _ => value,
+                };
+                Ok(Code::new(format!("{}≠0", measured.wrap(super::RELATIONAL)), super::RELATIONAL))
+            }
+            // the key pressed since the last call, or 0, so every call reads a new value
+            "getKey" => Ok(Code::atom("getKey".to_string())),
+            "int" | "float" => {
+                let type_ = self.type_of(&args[0])?;
+                let code = self.expression(&args[0])?;
+                Ok(match type_ {
+                    Type::String => Code::atom(format!("expr({})", code.text)),
+                    // Python's int() truncates towards zero like iPart(, where int( would floor
+                    Type::Float if name == "int" => Code::atom(format!("iPart({})", code.text)),
+                    _ => code,
+                })
+            }
+            _ => Err(format!("{}() does not return a value on line {}", name, line))
+        }
+    }
+
+    pub(super) fn builtin_type(&self, name: &str, args: &[Expression], line: usize) -> Result<Type, String> {
+        match name {
+            "disp" | "print" => Err(format!("{}() does not return a value on line {}", name, line)),
+            name if matrices::FUNCTIONS.contains(&name) => self.matrix_builtin_type(name, args, line),
+            "len" => {
+                arity(name, args, 1, line)?;
+                let type_ = self.type_of(&args[0])?;
+                match (&type_, super::storage(&type_)) {
+                    (Type::String | Type::RecordList(_), _) => Ok(Type::Int),
+                    (Type::Record(_), _) => Err(format!("len() of {:?} is not supported on line {}", type_, line)),
+                    (_, Storage::List) => Ok(Type::Int),
+                    _ => Err(format!("len() of {:?} is not supported on line {}", type_, line)),
+                }
+            }
+            "sum" | "sorted" => {
+                arity(name, args, 1, line)?;
+                let type_ = self.type_of(&args[0])?;
+                match super::element_type(&type_) {
+                    Some(_) if name == "sorted" && type_ == Type::ComplexList => Err(format!("complex numbers cannot be sorted on line {}", line)),
+                    Some(_) if name == "sorted" => Ok(type_),
+                    Some(element) => Ok(element),
+                    None => Err(format!("{}() expects a list, but found {:?} on line {}", name, type_, line)),
+                }
+            }
+            "abs" | "angle" => {
+                arity(name, args, 1, line)?;
+                let type_ = self.type_of(&args[0])?;
+                match (name, &type_) {
+                    ("abs", Type::Int | Type::Float | Type::IntList | Type::FloatList) => Ok(type_),
+                    (_, Type::Int | Type::Float | Type::Complex) => Ok(Type::Float),
+                    ("abs", Type::ComplexList) => Ok(Type::FloatList),
+                    _ => Err(format!("{}() of {:?} is not supported on line {}", name, type_, line)),
+                }
+            }
+            "Complex" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(format!("Complex() takes 1 or 2 arguments, but {} were given on line {}", args.len(), line));
+                }
+                for arg in args {
+                    let type_ = self.type_of(arg)?;
+                    if !matches!(type_, Type::Int | Type::Float) {
+                        return Err(format!("Complex() expects real parts, but found {:?} on line {}", type_, line));
+                    }
+                }
+                Ok(Type::Complex)
+            }
+            "min" | "max" => {
+                if args.is_empty() {
+                    return Err(format!("{}() expects at least 1 argument on line {}", name, line));
+                }
+                let types = args.iter().map(|arg| self.type_of(arg)).collect::<Result<Vec<Type>, String>>()?;
+                if let [list] = types.as_slice() {
+                    return match super::element_type(list) {
+                        Some(Type::Complex) | None => Err(format!("{}() of {:?} is not supported on line {}", name, list, line)),
+                        Some(element) => Ok(element),
+                    };
+                }
+                let mut widest = Type::Int;
+                for type_ in &types {
+                    widest = match super::widen(&widest, type_) {
+                        Some(widened @ (Type::Int | Type::Float)) => widened,
+                        _ => return Err(format!("{}() cannot compare {:?} on line {}", name, type_, line)),
+                    };
+                }
+                Ok(widest)
+            }
+            "bool" => {
+                arity(name, args, 1, line)?;
+                let type_ = self.type_of(&args[0])?;
+                if matches!(type_, Type::Matrix | Type::Record(_) | Type::RecordList(_) | Type::Tuple(_)) {
+                    return Err(format!("bool() of {:?} is not supported on line {}", type_, line));
+                }
+                Ok(Type::Bool)
+            }
+            "getKey" => {
+                arity(name, args, 0, line)?;
+                Ok(Type::Int)
+            }
+            "str" | "int" | "float" => {
+                arity(name, args, 1, line)?;
+                let type_ = self.type_of(&args[0])?;
+                if !matches!(type_, Type::Bool | Type::Int | Type::Float | Type::String) {
+                    return Err(format!("{}() of {:?} is not supported on line {}", name, type_, line));
+                }
+                Ok(match name {
+                    "str" => Type::String,
+                    "int" => Type::Int,
+                    _ => Type::Float,
+                })
+            }
+            _ => Err(format!("unknown function '{}' on line {}", name, line))
+        }
+    }
+
+    /// Lowers a method called for its effect, which is how lists are changed in place.
+    pub(super) fn method_statement(&mut self, receiver: &Expression, name: &str, args: &[Expression], line: usize) -> Result<(), String> {
+        let type_ = self.type_of(receiver)?;
+        if let (Type::RecordList(record), ExpressionType::Field(records)) = (&type_, receiver.type_.as_ref()) {
+            return self.records_method(records, record, name, args, line);
+        }
+        let field = match receiver.type_.as_ref() {
+            ExpressionType::Field(field) if super::storage(&type_) == Storage::List && !matches!(type_, Type::Record(_)) => field,
+            _ => {
+                self.method_type(receiver, name, args, line)?;
+                return Err(format!("result of .{}() is unused on line {}", name, line));
+            }
+        };
+        match name {
+            "append" | "extend" => {
+                arity(name, args, 1, line)?;
+                let argument = self.type_of(&args[0])?;
+                let added = match (name, super::element_type(&argument)) {
+                    ("append", None) => super::list_type(&argument),
+                    ("extend", Some(_)) => Some(argument.clone()),
+                    _ => None,
+                };
+                let widened = added.and_then(|added| super::widen(&type_, &added));
+                let Some(widened) = widened else {
+                    return Err(format!("cannot {} {:?} to {:?} on line {}", name, argument, type_, line));
+                };
+                let value = self.expression(&args[0])?;
+                let list = self.declare(field, &widened)?;
+                // storing one past the end grows a list by one element
+                if name == "append" {
+                    self.lines.push(format!("{}→{}(dim({})+1)", value.text, list, list));
+                } else {
+                    self.lines.push(format!("augment({},{})→{}", list, value.text, list));
+                }
+            }
+            "sort" | "clear" => {
+                arity(name, args, 0, line)?;
+                let list = self.expression(receiver)?;
+                if name == "clear" {
+                    self.lines.push(format!("0→dim({})", list.text));
+                } else if type_ == Type::ComplexList {
+                    return Err(format!("complex numbers cannot be sorted on line {}", line));
+                } else {
+                    self.lines.push(format!("SortA({})", list.text));
+                }
+            }
+            _ => return Err(format!("{:?} has no method '{}' on line {}", type_, name, line))
+        }
+        Ok(())
+    }
+
+    pub(super) fn attribute(&mut self, receiver: &Expression, name: &str, line: usize) -> Result<Code, String> {
+        if let Type::Record(_) = self.attribute_type(receiver, name, line).and(self.type_of(receiver))? {
+            return self.record_attribute(receiver, name, line);
+        }
+        match name {
+            "T" => {
+                let matrix = self.expression(receiver)?;
+                Ok(Code::new(format!("{}ᵀ", matrix.wrap(POSTFIX)), POSTFIX))
+            }
+            "real" | "imag" => {
+                let value = self.expression(receiver)?;
+                Ok(Code::atom(format!("{}({})", name, value.text)))
+            }
+            _ => unreachable!()
+        }
+    }
+
+    pub(super) fn attribute_type(&self, receiver: &Expression, name: &str, line: usize) -> Result<Type, String> {
+        let type_ = self.type_of(receiver)?;
+        match (&type_, name) {
+            (Type::Record(record), _) => self.field_type(record, name, line),
+            (Type::Matrix, "T") => {
+                self.shape(receiver)?;
+                Ok(Type::Matrix)
+            }
+            (Type::Int | Type::Float, "real" | "imag") => Ok(type_),
+            (Type::Complex, "real" | "imag") => Ok(Type::Float),
+            _ => Err(format!("{:?} has no attribute '{}' on line {}", type_, name, line))
+        }
+    }
+
+    pub(super) fn method(&mut self, receiver: &Expression, name: &str, args: &[Expression], line: usize) -> Result<Code, String> {
+        self.method_type(receiver, name, args, line)?;
+        match name {
+            // inString( is 1-based and returns 0 when missing, so subtracting one matches str.find
+            "find" => {
+                let haystack = self.expression(receiver)?;
+                let needle = self.expression(&args[0])?;
+                Ok(Code::new(format!("inString({},{})-1", haystack.text, needle.text), ADDITIVE))
+            }
+            "conjugate" => {
+                let value = self.expression(receiver)?;
+                Ok(Code::atom(format!("conj({})", value.text)))
+            }
+            _ => unreachable!()
+        }
+    }
+
+    pub(super) fn method_type(&self, receiver: &Expression, name: &str, args: &[Expression], line: usize) -> Result<Type, String> {
+        let type_ = self.type_of(receiver)?;
+        match (&type_, name) {
+            (Type::String, "find") => {
+                arity(name, args, 1, line)?;
+                let argument = self.type_of(&args[0])?;
+                if argument != Type::String {
+                    return Err(format!("str.find() expects a str, but found {:?} on line {}", argument, line));
+                }
+                Ok(Type::Int)
+            }
+            (Type::Int | Type::Float | Type::Complex, "conjugate") => {
+                arity(name, args, 0, line)?;
+                Ok(type_)
+            }
+            (Type::IntList | Type::FloatList | Type::ComplexList, "append" | "extend" | "sort" | "clear") |
+            (Type::RecordList(_), "append" | "clear") => {
+                Err(format!("list.{}() does not return a value on line {}", name, line))
+            }
+            _ => Err(format!("{:?} has no method '{}' on line {}", type_, name, line))
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::statements::{Expression, ExpressionType};
+use crate::types::Type;
+use super::{integer, scopes, storage, widen, Code, Generator, Program, Variable};
+
+/// The program calling every function and lambda of one signature. TI-Basic cannot call a program named by a
+/// variable, so a `Callable` holds an ID instead, and the dispatch program runs the branch with that ID.
+pub(super) struct Dispatcher {
+    signature: Type,
+    branches: Vec<(usize, Vec<String>)>,
+}
+
+impl Generator<'_> {
+
+    /// Checks that a `Callable` only takes and returns values its dispatch program can pass along.
+    pub(super) fn check_signature(&self, parameters: &[Type], returned: Option<&Type>) -> Result<(), String> {
+        for parameter in parameters {
+            if !matches!(parameter, Type::Bool | Type::Int | Type::Float | Type::Complex | Type::String |
+                Type::IntList | Type::FloatList | Type::ComplexList) {
+                return Err(format!("a Callable cannot take {:?}", parameter));
+            }
+        }
+        match returned {
+            None | Some(Type::Bool | Type::Int | Type::Float | Type::Complex) => Ok(()),
+            Some(type_) => Err(format!("a Callable cannot return {:?}", type_)),
+        }
+    }
+
+    /// Whether `name` is a variable holding a function or lambda.
+    pub(super) fn is_callable(&self, name: &str) -> bool {
+        matches!(self.variables.get(&self.key(name)), Some(Variable { type_: Type::Callable(_, _), .. }))
+    }
+
+    /// The type of a reference to the user function `name`.
+    pub(super) fn signature(&self, name: &str, line: usize) -> Result<Type, String> {
+        let function = self.functions.iter().find(|function| function.name == name).unwrap();
+        let signature = Type::Callable(
+            function.parameters.iter().map(|parameter| parameter.typetype.clone()).collect(),
+            function.return_type.clone().map(Box::new),
+        );
+        self.check_type(&signature).map_err(|error| format!("'{}' cannot be referenced, as {} on line {}", name, error, line))?;
+        Ok(signature)
+    }
+
+    /// Lowers a reference to the user function `name` to its ID, adding a branch calling it to its dispatch program.
+    pub(super) fn reference(&mut self, name: &str, line: usize) -> Result<Code, String> {
+        let signature = self.signature(name, line)?;
+        let id = self.functions.iter().position(|function| function.name == name).unwrap() + 1;
+        let dispatcher = self.dispatcher(&signature)?;
+        if self.dispatchers[dispatcher].branches.iter().any(|(other, _)| *other == id) {
+            return Ok(integer(id as i64));
+        }
+        let functions = self.functions;
+        let mut lines = Vec::new();
+        for (i, parameter) in functions[id - 1].parameters.iter().enumerate() {
+            let slot = self.slot(i, &parameter.typetype)?;
+            let variable = self.declare(&scopes::local(name, &parameter.name), &parameter.typetype)?;
+            lines.push(format!("{}→{}", slot, variable));
+        }
+        lines.push(format!("prgm{}", self.programs[name]));
+        self.dispatchers[dispatcher].branches.push((id, lines));
+        Ok(integer(id as i64))
+    }
+
+    /// Lowers `value` where a `Callable` of type `expected` is wanted, compiling a lambda into a new branch.
+    pub(super) fn callable(&mut self, value: &Expression, expected: &Type) -> Result<Code, String> {
+        let line = value.line_number;
+        let ExpressionType::Lambda(parameters, body) = value.type_.as_ref() else {
+            return self.expression(value);
+        };
+        let Type::Callable(types, returned) = expected else {
+            return Err(format!("a lambda cannot be used as {:?} on line {}", expected, line));
+        };
+        if parameters.len() != types.len() {
+            return Err(format!("the lambda takes {} parameters, but {:?} passes {} on line {}", parameters.len(), expected, types.len(), line));
+        }
+        let id = self.functions.len() + 1 + self.lambdas;
+        self.lambdas += 1;
+        let dispatcher = self.dispatcher(expected)?;
+        let ((), lines) = self.capture(|generator| generator.lambda(id, parameters, types, returned.as_deref(), body))?;
+        self.dispatchers[dispatcher].branches.push((id, lines));
+        Ok(integer(id as i64))
+    }
+
+    /// Lowers the body of a lambda, in the scope it was written in but with its parameters read from the argument
+    /// slots. A body calling other programs copies the slots first, as a nested call through a `Callable` reuses them.
+    fn lambda(&mut self, id: usize, parameters: &[String], types: &[Type], returned: Option<&Type>, body: &Expression) -> Result<(), String> {
+        let copied = self.calls(body);
+        let mut shadowed = Vec::new();
+        for (i, (name, type_)) in parameters.iter().zip(types).enumerate() {
+            let mut variable = self.slot(i, type_)?;
+            if copied {
+                let copy = self.declare(&format!("$lambda{}$argument{}{:?}", id, i, storage(type_)), type_)?;
+                self.lines.push(format!("{}→{}", variable, copy));
+                variable = copy;
+            }
+            let key = self.key(name);
+            let previous = self.variables.insert(key.clone(), Variable { name: variable, type_: type_.clone() });
+            shadowed.push((key, previous));
+        }
+        let result = self.lambda_body(returned, body);
+        for (key, previous) in shadowed.into_iter().rev() {
+            match previous {
+                Some(previous) => self.variables.insert(key, previous),
+                None => self.variables.remove(&key),
+            };
+        }
+        result
+    }
+
+    fn lambda_body(&mut self, returned: Option<&Type>, body: &Expression) -> Result<(), String> {
+        let line = body.line_number;
+        self.bind_comprehensions(body)?;
+        let Some(returned) = returned else {
+            return self.ignored(body);
+        };
+        let actual = self.type_of(body)?;
+        if widen(returned, &actual).as_ref() != Some(returned) {
+            return Err(format!("the lambda should return {:?}, but returns {:?} on line {}", returned, actual, line));
+        }
+        let code = self.value(body)?;
+        if code.text != "Ans" {
+            self.lines.push(code.text);
+        }
+        Ok(())
+    }
+
+    /// Lowers a call through the `Callable` variable `name`, leaving any result in `Ans`.
+    pub(super) fn indirect(&mut self, name: &str, args: &[Expression], line: usize) -> Result<Option<Type>, String> {
+        let variable = self.lookup(name, line)?;
+        let (code, signature) = (variable.name.clone(), variable.type_.clone());
+        let Type::Callable(types, returned) = &signature else { unreachable!() };
+        if let Some(keyword) = args.iter().find(|arg| matches!(arg.type_.as_ref(), ExpressionType::Keyword(_, _))) {
+            return Err(format!("'{}' cannot be called with keyword arguments on line {}", name, keyword.line_number));
+        }
+        if args.len() != types.len() {
+            return Err(format!("'{}' takes {} arguments, but {} were given on line {}", name, types.len(), args.len(), line));
+        }
+        let mut stores = Vec::new();
+        for (i, (arg, type_)) in args.iter().zip(types).enumerate() {
+            if !matches!(arg.type_.as_ref(), ExpressionType::Lambda(_, _)) {
+                let actual = self.type_of(arg)?;
+                if widen(type_, &actual).as_ref() != Some(type_) {
+                    return Err(format!("argument {} of '{}' should be {:?}, but found {:?} on line {}", i + 1, name, type_, actual, line));
+                }
+            }
+            let code = self.callable(arg, type_)?;
+            let slot = self.slot(i, type_)?;
+            let mut reads = Vec::new();
+            self.reads(arg, &mut reads);
+            stores.push((code, slot, reads, type_.clone()));
+        }
+        self.store_arguments(stores)?;
+        let id = self.declare("$call$id", &Type::Int)?;
+        self.lines.push(format!("{}→{}", code, id));
+        let dispatcher = self.dispatcher(&signature)?;
+        self.lines.push(format!("prgmCRCALL{}", dispatcher + 1));
+        Ok(returned.as_deref().cloned())
+    }
+
+    /// The variable passing argument `i` of a `Callable` of any signature.
+    fn slot(&mut self, i: usize, type_: &Type) -> Result<String, String> {
+        self.declare(&format!("$call$argument{}{:?}", i, storage(type_)), type_)
+    }
+
+    /// The index of the dispatch program for `signature`, registering it on first use.
+    fn dispatcher(&mut self, signature: &Type) -> Result<usize, String> {
+        if let Some(i) = self.dispatchers.iter().position(|dispatcher| dispatcher.signature == *signature) {
+            return Ok(i);
+        }
+        let name = format!("CRCALL{}", self.dispatchers.len() + 1);
+        if self.programs.values().any(|program| *program == name) {
+            return Err(format!("prgm{} is reserved for one of Crunch's dispatch programs, rename the function compiling to it", name));
+        }
+        self.dispatchers.push(Dispatcher { signature: signature.clone(), branches: Vec::new() });
+        Ok(self.dispatchers.len() - 1)
+    }
+
+    /// Whether evaluating `expression` runs another program.
+    fn calls(&self, expression: &Expression) -> bool {
+        let call = matches!(expression.type_.as_ref(), ExpressionType::Call(name, _) if self.programs.contains_key(name) || self.is_callable(name));
+        call || super::children(expression).into_iter().any(|child| self.calls(child))
+    }
+
+    /// Builds the dispatch programs. Each branch returns straight away, as the program it calls may change the ID.
+    pub(super) fn dispatch_programs(&mut self) -> Result<Vec<Program>, String> {
+        if self.dispatchers.is_empty() {
+            return Ok(Vec::new());
+        }
+        let id = self.declare("$call$id", &Type::Int)?;
+        let dispatchers = std::mem::take(&mut self.dispatchers);
+        let mut programs = Vec::new();
+        for (i, dispatcher) in dispatchers.into_iter().enumerate() {
+            let mut lines = Vec::new();
+            let count = dispatcher.branches.len();
+            for (j, (branch, body)) in dispatcher.branches.into_iter().enumerate() {
+                // the last branch is the only one left
+                if j + 1 == count {
+                    lines.extend(body);
+                    break;
+                }
+                lines.push(format!("If {}={}", id, branch));
+                lines.push("Then".to_string());
+                lines.extend(body);
+                lines.push("Return".to_string());
+                lines.push("End".to_string());
+            }
+            programs.push(Program { name: format!("CRCALL{}", i + 1), lines });
+        }
+        Ok(programs)
+    }
+}
@@ -0,0 +1,339 @@
+use crate::statements::{Expression, ExpressionType, Handler, Operation, Statement, StatementType};
+use crate::types::Type;
+use super::{builtins, storage, Generator, Storage};
+
+/// What the failure flag of a `try` holds once a check fails, for each exception Crunch can check for.
+const ZERO_DIVISION: usize = 1;
+const INDEX: usize = 2;
+
+impl Generator<'_> {
+
+    /// Lowers `try` by checking, before the block runs, that none of its divisions or indexing can fail. The
+    /// handler then runs instead of the whole block, rather than after the statements before the failure.
+    pub(super) fn try_statement(&mut self, body: &[Statement], handlers: &[Handler], otherwise: &[Statement], finally: &[Statement], line: usize) -> Result<(), String> {
+        let caught = handlers.iter().map(|(names, _)| exceptions(names, line)).collect::<Result<Vec<Vec<usize>>, String>>()?;
+        let catching: Vec<usize> = caught.iter().flatten().copied().collect();
+        if !finally.is_empty() && [body, otherwise].into_iter().chain(handlers.iter().map(|(_, handler)| handler.as_slice())).any(returns) {
+            return Err(format!("'return' inside a try block with 'finally' is not supported on line {}", line));
+        }
+        let mut body = body.to_vec();
+        let mut steps = Vec::new();
+        let mut written = Written::default();
+        for statement in &mut body {
+            if !super::nested(statement).is_empty() {
+                if raises(statement, &catching) {
+                    return Err(format!("only operations outside of nested blocks can be checked by a try block on line {}", statement.line_number));
+                }
+                continue;
+            }
+            let changed = changes(statement);
+            for expression in super::expressions_mut(statement) {
+                self.risks(expression, &catching, &mut written, &mut steps)?;
+            }
+            written.names.extend(changed);
+        }
+        let checked = steps.iter().any(|step| matches!(step, Step::Check(_, _)));
+        if !checked && !handlers.is_empty() {
+            crate::warning(&format!("nothing in the try block on line {} can raise the exceptions it catches", line));
+        }
+        let ((), mut protected) = self.capture(|generator| generator.statements(&body))?;
+        let ((), rest) = self.capture(|generator| generator.statements(otherwise))?;
+        protected.extend(rest);
+        if !checked {
+            self.hoist(&steps, None)?;
+            self.lines.extend(protected);
+        } else if handlers.len() == 1 && steps.iter().all(|step| self.is_safe(step.expression())) {
+            self.hoist(&steps, None)?;
+            let condition = steps.into_iter().filter_map(|step| match step {
+                Step::Check(check, _) => Some(check),
+                Step::Hoist(_, _) => None,
+            }).reduce(|left, right| Expression::new(ExpressionType::Operation(Operation::And(left, right)), line)).unwrap();
+            let code = self.condition(&condition)?;
+            let ((), handler) = self.capture(|generator| generator.statements(&handlers[0].1))?;
+            let handler = if handler.is_empty() { None } else { Some(handler) };
+            self.block(format!("If {}", code.text), protected, handler);
+        } else {
+            // each step only runs while the checks before it passed, as a failed check could make the next one error
+            let key = format!("{}$raised", self.function);
+            let flag = self.declare(&key, &Type::Int)?;
+            let flag = (Expression::new(ExpressionType::Field(key), line), flag);
+            self.lines.push(format!("0→{}", flag.1));
+            let mut first = true;
+            for step in steps {
+                match step {
+                    Step::Hoist(_, ref value) if first || self.is_safe(value) => self.hoist(std::slice::from_ref(&step), None)?,
+                    Step::Hoist(_, _) => self.hoist(std::slice::from_ref(&step), Some(&equals(&flag.0, 0)))?,
+                    Step::Check(check, exception) => {
+                        let failed = Expression::new(ExpressionType::Operation(super::negation(&check)), line);
+                        let condition = match first {
+                            true => failed,
+                            false => Expression::new(ExpressionType::Operation(Operation::And(equals(&flag.0, 0), failed)), line),
+                        };
+                        self.guarded(&condition, vec![format!("{}→{}", exception, flag.1)])?;
+                        first = false;
+                    }
+                }
+            }
+        let ((), handled) = self.capture(|generator| generator.handlers(&flag.0, handlers, &caught))?;
+            let code = self.condition(&equals(&flag.0, 0))?;
+            let handled = if handled.is_empty() { None } else { Some(handled) };
+            self.block(format!("If {}", code.text), protected, handled);
+        }
+        self.statements(finally)
+    }
+
+    /// Lowers the `except` clauses to nested `If`/`Else` blocks on the failure flag.
+    fn handlers(&mut self, flag: &Expression, handlers: &[Handler], caught: &[Vec<usize>]) -> Result<(), String> {
+        let Some(((_, body), rest)) = handlers.split_first() else {
+            return Ok(());
+        };
+        let line = flag.line_number;
+        let condition = caught[0].iter().map(|exception| equals(flag, *exception))
+            .reduce(|left, right| Expression::new(ExpressionType::Operation(Operation::Or(left, right)), line)).unwrap();
+        let code = self.condition(&condition)?;
+        let ((), body) = self.capture(|generator| generator.statements(body))?;
+        let ((), else_body) = self.capture(|generator| generator.handlers(flag, rest, &caught[1..]))?;
+        let else_body = if else_body.is_empty() { None } else { Some(else_body) };
+        self.block(format!("If {}", code.text), body, else_body);
+        Ok(())
+    }
+
+    /// Collects a check for each operation in `expression` that could raise one of the `catching` exceptions,
+    /// inner operations first, as they are evaluated first. The checks run before the block, so they must not read
+    /// anything `written` earlier in it. An operand that is not a variable is stored before the checks and read
+    /// back by both the check and the operation, so it is only evaluated once.
+    fn risks(&mut self, expression: &mut Expression, catching: &[usize], written: &mut Written, steps: &mut Vec<Step>) -> Result<(), String> {
+        for child in super::children_mut(expression) {
+            self.risks(child, catching, written, steps)?;
+        }
+        if self.writes(expression) {
+            written.call.get_or_insert(expression.line_number);
+        }
+        let line = expression.line_number;
+        let operation = |operation: Operation| Expression::new(ExpressionType::Operation(operation), line);
+        let ExpressionType::Operation(risky) = expression.type_.as_mut() else {
+            return Ok(());
+        };
+        match risky {
+            Operation::Div(_, divisor) | Operation::FloorDiv(_, divisor) | Operation::Mod(_, divisor) if catching.contains(&ZERO_DIVISION) => {
+                if super::evaluate(divisor, &Default::default()).is_some_and(|value| value != 0.0) {
+                    return Ok(());
+                }
+                let type_ = self.type_of(divisor)?;
+                if !matches!(storage(&type_), Storage::Real | Storage::List) {
+                    return Ok(());
+                }
+                let divisor = self.operand(divisor, &type_, written, steps)?;
+                let check = match storage(&type_) {
+                    Storage::Real => operation(Operation::NotEqual(divisor, super::literal(0, line))),
+                    _ => operation(Operation::Not(operation(Operation::In(super::literal(0, line), divisor)))),
+                };
+                steps.push(Step::Check(check, ZERO_DIVISION));
+            }
+            Operation::ArrayIndex(list, index) if catching.contains(&INDEX) => {
+                let matrix = match list.type_.as_ref() {
+                    ExpressionType::Operation(Operation::ArrayIndex(inner, _)) => self.type_of(inner)? == Type::Matrix,
+                    _ => self.type_of(list)? == Type::Matrix,
+                };
+                if matrix {
+                    return Ok(());
+                }
+                if !is_variable(list) {
+                    return Err(format!("only indexing into a variable can be checked by a try block on line {}", line));
+                }
+                written.check(list, line)?;
+                let length = Expression::new(ExpressionType::Call("len".to_string(), vec![list.clone()]), line);
+                let check = match super::integer_literal(index) {
+                    Some(i) if i < 0 => operation(Operation::GreaterEquals(length, super::literal(-i, line))),
+                    Some(i) => operation(Operation::GreaterThan(length, super::literal(i, line))),
+                    None if super::is_natural(index) => {
+                        let index = self.operand(index, &Type::Int, written, steps)?;
+                        operation(Operation::LessThan(index, length))
+                    }
+                    // a negative index counts back from the end
+                    None => {
+                        let index = self.operand(index, &Type::Int, written, steps)?;
+                        operation(Operation::And(
+                            operation(Operation::GreaterEquals(index.clone(), operation(Operation::Negative(length.clone())))),
+                            operation(Operation::LessThan(index, length)),
+                        ))
+                    }
+                };
+                steps.push(Step::Check(check, INDEX));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The expression a check reads `operand` through. Anything but a variable or a literal is stored in a temporary
+    /// first, which `operand` is replaced with, so the block reads the value that was checked.
+    fn operand(&mut self, operand: &mut Expression, type_: &Type, written: &Written, steps: &mut Vec<Step>) -> Result<Expression, String> {
+        let line = operand.line_number;
+        written.check(operand, line)?;
+        if is_variable(operand) || super::evaluate(operand, &Default::default()).is_some() {
+            return Ok(operand.clone());
+        }
+        if self.effects(operand) {
+            return Err(format!("an operand with side effects cannot be checked before the try block runs on line {}, store it in a variable before the block", line));
+        }
+        let key = self.temp_key(type_);
+        let temp = self.declare(&key, type_)?;
+        let value = std::mem::replace(operand, Expression::new(ExpressionType::Field(key), line));
+        steps.push(Step::Hoist(temp, value));
+        Ok(operand.clone())
+    }
+
+    /// Stores the operands hoisted by `steps` in their temporaries, only once `guard` holds if there is one.
+    fn hoist(&mut self, steps: &[Step], guard: Option<&Expression>) -> Result<(), String> {
+        for step in steps {
+            let Step::Hoist(temp, value) = step else {
+                continue;
+            };
+            let (code, mut lines) = self.capture(|generator| generator.expression(value))?;
+            lines.push(format!("{}→{}", code.text, temp));
+            match guard {
+                Some(guard) => self.guarded(guard, lines)?,
+                None => self.lines.extend(lines),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether evaluating `expression` could change a variable, through a function it calls or a method.
+    fn writes(&self, expression: &Expression) -> bool {
+        match expression.type_.as_ref() {
+            ExpressionType::Call(name, _) => self.programs.contains_key(name) || self.is_callable(name),
+            ExpressionType::Method(_, _, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether evaluating `expression` could do anything besides producing its value or erroring.
+    fn effects(&self, expression: &Expression) -> bool {
+        let effects = match expression.type_.as_ref() {
+            ExpressionType::Call(name, _) => self.writes(expression) || self.record(name).is_none() && !builtins::is_pure(name),
+            ExpressionType::Operation(Operation::Increment(_) | Operation::Decrement(_)) => true,
+            _ => self.writes(expression),
+        };
+        effects || super::children(expression).into_iter().any(|child| self.effects(child))
+    }
+}
+
+/// Something done before a `try` block runs: checking that an operation in it cannot fail, or storing an operand
+/// in a temporary so it is evaluated once.
+enum Step {
+    Check(Expression, usize),
+    Hoist(String, Expression),
+}
+
+impl Step {
+    fn expression(&self) -> &Expression {
+        match self {
+            Step::Check(check, _) => check,
+            Step::Hoist(_, value) => value,
+        }
+    }
+}
+
+/// What the statements of a `try` block lowered so far may have changed.
+#[derive(Default)]
+struct Written {
+    names: Vec<String>,
+    call: Option<usize>, // the line of the first call, which could change any variable
+}
+
+impl Written {
+    /// Rejects checking `expression` before the block if the block may change what it reads first.
+    fn check(&self, expression: &Expression, line: usize) -> Result<(), String> {
+        let names = names(expression);
+        if let Some(name) = names.iter().find(|name| self.names.contains(name)) {
+            return Err(format!("'{}' changes inside the try block, so it cannot be checked before the block runs on line {}", name, line));
+        }
+        match (names.first(), self.call) {
+            (Some(name), Some(call)) => Err(format!("'{}' may be changed by the call on line {}, so it cannot be checked before the try block runs on line {}", name, call, line)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `expression` names a variable or a field of one.
+fn is_variable(expression: &Expression) -> bool {
+    match expression.type_.as_ref() {
+        ExpressionType::Field(_) => true,
+        ExpressionType::Attribute(receiver, _) => is_variable(receiver),
+        _ => false,
+    }
+}
+
+/// Whether anything in `statement` could raise one of the `catching` exceptions, before any types are known.
+fn raises(statement: &Statement, catching: &[usize]) -> bool {
+    fn risky(expression: &Expression, catching: &[usize]) -> bool {
+        let raises = match expression.type_.as_ref() {
+            ExpressionType::Operation(Operation::Div(_, divisor) | Operation::FloorDiv(_, divisor) | Operation::Mod(_, divisor)) => {
+                catching.contains(&ZERO_DIVISION) && !super::evaluate(divisor, &Default::default()).is_some_and(|value| value != 0.0)
+            }
+            ExpressionType::Operation(Operation::ArrayIndex(_, _)) => catching.contains(&INDEX),
+            _ => false,
+        };
+        raises || super::children(expression).into_iter().any(|child| risky(child, catching))
+    }
+    super::expressions(statement).into_iter().any(|expression| risky(expression, catching))
+        || super::nested(statement).into_iter().flatten().any(|statement| raises(statement, catching))
+}
+
+/// The exceptions an `except` clause catches, where a bare `except` catches all of them.
+fn exceptions(names: &[String], line: usize) -> Result<Vec<usize>, String> {
+    if names.is_empty() {
+        return Ok(vec![ZERO_DIVISION, INDEX]);
+    }
+    let mut caught = Vec::new();
+    for name in names {
+        caught.extend(match name.as_str() {
+            "ZeroDivisionError" | "ArithmeticError" => vec![ZERO_DIVISION],
+            "IndexError" | "LookupError" => vec![INDEX],
+            "Exception" | "BaseException" => vec![ZERO_DIVISION, INDEX],
+            _ => return Err(format!("only ZeroDivisionError and IndexError can be caught, but found '{}' on line {}", name, line))
+        });
+    }
+    Ok(caught)
+}
+
+fn equals(flag: &Expression, value: usize) -> Expression {
+    let line = flag.line_number;
+    Expression::new(ExpressionType::Operation(Operation::Equals(flag.clone(), super::literal(value as i64, line))), line)
+}
+
+fn returns(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| matches!(statement.type_.as_ref(), StatementType::Return(_)) || super::nested(statement).into_iter().any(returns))
+}
+
+/// The variables `expression` reads.
+fn names(expression: &Expression) -> Vec<String> {
+    let mut names = Vec::new();
+    if let ExpressionType::Field(name) = expression.type_.as_ref() {
+        names.push(name.clone());
+    }
+    for child in super::children(expression) {
+        names.extend(self::names(child));
+    }
+    names
+}
+
+/// The variables `statement` assigns or changes in place.
+fn changes(statement: &Statement) -> Vec<String> {
+    fn root(target: &Expression) -> Vec<String> {
+        match target.type_.as_ref() {
+            ExpressionType::Field(name) => vec![name.clone()],
+            ExpressionType::Tuple(targets) => targets.iter().flat_map(root).collect(),
+            ExpressionType::Operation(Operation::ArrayIndex(inner, _)) | ExpressionType::Attribute(inner, _) |
+            ExpressionType::Method(inner, _, _) => root(inner),
+            _ => Vec::new(),
+        }
+    }
+    match statement.type_.as_ref() {
+        StatementType::Assignment(target, _) | StatementType::Declaration(target, _, _) |
+        StatementType::Del(target) | StatementType::Ignored(target) => root(target),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use crate::statements::{Expression, ExpressionType, Operation, Statement, StatementType};
+use crate::types::Type;
+use super::{element_type, evaluate, negation, nested, Generator};
+
+/// The flags the body of a loop sets on `break` and `continue`. TI-Basic has neither, and jumping out of a loop
+/// with `Goto` leaks memory, so the rest of the body is skipped by testing the flags instead.
+pub(super) struct Exit {
+    broken: Option<String>,
+    skipped: Option<String>,
+    index: Option<(String, String)>, // the index of a `for` loop and the list it runs over
+}
+
+impl Generator<'_> {
+
+    /// Lowers a `while` loop to the cheapest form that keeps its meaning: `While 1` when it never ends by itself,
+    /// and `Repeat`, which only tests at `End`, when the values `known` on entry make the first test pass anyway.
+    /// A `while True:` ending in `if condition: break` becomes `Repeat condition`.
+    pub(super) fn while_loop(&mut self, condition: &Expression, body: &[Statement], known: &HashMap<String, f64>) -> Result<(), String> {
+        let line = condition.line_number;
+        let always = evaluate(condition, &HashMap::new());
+        if always == Some(0.0) {
+            return Ok(());
+        }
+        let (body, until) = match (always, until(body)) {
+            (Some(_), Some((rest, until))) => (rest, Some(until)),
+            _ => (body, None),
+        };
+        let exit = self.exit(body, None)?;
+        let flag = exit.broken.clone().map(|key| Expression::new(ExpressionType::Field(key), line));
+        let operation = |operation: Operation| Expression::new(ExpressionType::Operation(operation), line);
+        let (header, setup) = if let Some(until) = until {
+            let (code, setup) = self.capture(|generator| generator.condition(until))?;
+            (format!("Repeat {}", code.text), setup)
+        } else if always.is_some() {
+            match &flag {
+                Some(flag) => {
+                    let (code, setup) = self.capture(|generator| generator.condition(flag))?;
+                    (format!("Repeat {}", code.text), setup)
+                }
+                None => ("While 1".to_string(), Vec::new()),
+            }
+        } else if evaluate(condition, known).is_some_and(|value| value != 0.0) {
+            let exit = operation(negation(condition));
+            let exit = match flag {
+                Some(flag) => operation(Operation::Or(flag, exit)),
+                None => exit,
+            };
+            let (code, setup) = self.capture(|generator| generator.condition(&exit))?;
+            (format!("Repeat {}", code.text), setup)
+        } else {
+            let condition = match flag {
+                Some(flag) => operation(Operation::And(operation(Operation::Not(flag)), condition.clone())),
+                None => condition.clone(),
+            };
+            let (code, setup) = self.capture(|generator| generator.condition(&condition))?;
+            // unlike Repeat, While tests before the first pass too
+            self.lines.extend(setup.clone());
+            (format!("While {}", code.text), setup)
+        };
+        let mut body = self.looped(body, exit)?;
+        body.extend(setup);
+        self.lines.push(header);
+        self.lines.extend(body);
+        self.lines.push("End".to_string());
+        Ok(())
+    }
+
+    pub(super) fn for_loop(&mut self, variable: &str, list: &str, body: &[Statement], line: usize) -> Result<(), String> {
+        let looped = self.lookup(list, line)?;
+        let (name, element) = match (element_type(&looped.type_), &looped.type_) {
+            (Some(element), _) => (looped.name.clone(), element),
+            (None, Type::RecordList(record)) => (looped.name.clone(), Type::Record(record.clone())),
+            (None, _) => return Err(format!("cannot iterate over '{}' of type {:?} on line {}", list, looped.type_, line))
+        };
+        let variable = self.declare(variable, &element)?;
+        let index = self.declare(&format!("{}$index{}", self.function, self.loops), &Type::Int)?;
+        let exit = self.exit(body, Some((index.clone(), name.clone())))?;
+        self.loops += 1;
+        let body = self.looped(body, exit);
+        self.loops -= 1;
+        let body = body?;
+        self.lines.push(format!("For({},1,dim({}))", index, name));
+        // records are copied into the loop variable, so changing its fields leaves the list as it was
+        if let Type::Record(_) = element {
+            let copy = self.record_iteration(list, &variable, &index, line)?;
+            self.lines.push(copy);
+        } else {
+            self.lines.push(format!("{}({})→{}", name, index, variable));
+        }
+        self.lines.extend(body);
+        self.lines.push("End".to_string());
+        Ok(())
+    }
+
+    /// Lowers `break` or `continue` by setting the innermost loop's flag. `break` also moves the index of a `for`
+    /// loop to its last element, so `End` leaves the loop.
+    pub(super) fn exit_loop(&mut self, breaking: bool, line: usize) -> Result<(), String> {
+        let Some(exit) = self.exits.last() else {
+            return Err(format!("'{}' outside loop on line {}", if breaking { "break" } else { "continue" }, line));
+        };
+        let (key, index) = match breaking {
+            true => (exit.broken.clone().unwrap(), exit.index.clone()),
+            false => (exit.skipped.clone().unwrap(), None),
+        };
+        let flag = self.declare(&key, &Type::Bool)?;
+        self.lines.push(format!("1→{}", flag));
+        if let Some((index, list)) = index {
+            self.lines.push(format!("dim({})→{}", list, index));
+        }
+        Ok(())
+    }
+
+    /// Lowers the statements that follow `statement` in a loop only while no `break` or `continue` in it has run.
+    /// Returns whether it lowered them.
+    pub(super) fn after_exit(&mut self, statement: &Statement, rest: &[Statement]) -> Result<bool, String> {
+        let Some(exit) = self.exits.last() else {
+            return Ok(false);
+        };
+        let (breaks, continues) = exits(std::slice::from_ref(statement));
+        if rest.is_empty() || !breaks && !continues {
+            return Ok(false);
+        }
+        // nothing after a `break` or `continue` in the same block can run
+        if matches!(statement.type_.as_ref(), StatementType::Break | StatementType::Continue) {
+            return Ok(true);
+        }
+        let keys: Vec<String> = [exit.broken.clone().filter(|_| breaks), exit.skipped.clone().filter(|_| continues)].into_iter().flatten().collect();
+        let flags = keys.iter().map(|key| self.declare(key, &Type::Bool)).collect::<Result<Vec<String>, String>>()?;
+        let ((), body) = self.capture(|generator| generator.statements(rest))?;
+        self.block(format!("If not({})", flags.join(" or ")), body, None);
+        Ok(true)
+    }
+
+    /// Allocates the flags `body` needs, clearing the `break` flag before the loop starts.
+    fn exit(&mut self, body: &[Statement], index: Option<(String, String)>) -> Result<Exit, String> {
+        let (breaks, continues) = exits(body);
+        let depth = self.exits.len();
+        let broken = match breaks {
+            true => {
+                let key = format!("{}$break{}", self.function, depth);
+                let flag = self.declare(&key, &Type::Bool)?;
+                self.lines.push(format!("0→{}", flag));
+                Some(key)
+            }
+            false => None,
+        };
+        let skipped = continues.then(|| format!("{}$continue{}", self.function, depth));
+        Ok(Exit { broken, skipped, index })
+    }
+
+    /// Lowers the body of a loop, clearing the `continue` flag at the start of every pass.
+    fn looped(&mut self, body: &[Statement], exit: Exit) -> Result<Vec<String>, String> {
+        let skipped = exit.skipped.clone();
+        self.exits.push(exit);
+        let result = self.capture(|generator| {
+            if let Some(key) = &skipped {
+                let flag = generator.declare(key, &Type::Bool)?;
+                generator.lines.push(format!("0→{}", flag));
+            }
+            generator.statements(body)
+        });
+        self.exits.pop();
+        Ok(result?.1)
+    }
+}
+
+/// Splits a loop body ending in `if condition: break` into the statements before it and the condition, when
+/// nothing else leaves the loop. A `continue` would skip the test, which `Repeat` makes at `End`.
+fn until(body: &[Statement]) -> Option<(&[Statement], &Expression)> {
+    let (last, rest) = body.split_last()?;
+    let StatementType::If(condition, inner, None) = last.type_.as_ref() else {
+        return None;
+    };
+    let breaks = matches!(inner.as_slice(), [statement] if matches!(statement.type_.as_ref(), StatementType::Break));
+    (breaks && exits(rest) == (false, false)).then_some((rest, condition))
+}
+
+/// Whether `statements` break out of and continue the loop they are in, leaving out the bodies of nested loops.
+fn exits(statements: &[Statement]) -> (bool, bool) {
+    statements.iter().fold((false, false), |(breaks, continues), statement| match statement.type_.as_ref() {
+        StatementType::Break => (true, continues),
+        StatementType::Continue => (breaks, true),
+        StatementType::While(_, _) | StatementType::For(_, _, _) => (breaks, continues),
+        _ => nested(statement).into_iter().map(exits).fold((breaks, continues), |(breaks, continues), (inner, skips)| (breaks || inner, continues || skips)),
+    })
+}
@@ -0,0 +1,131 @@
+use crate::statements::{Case, Expression, ExpressionType, Operation};
+use crate::types::Type;
+use super::{storage, Generator};
+
+/// Matches with at least this many cases test every case up front and jump to the one that matched, instead of
+/// nesting an `Else` per case that TI-Basic has to skip over one block at a time.
+const DISPATCH_CASES: usize = 5;
+
+/// Integer patterns with at least this many consecutive values are tested as a range.
+const RANGE_VALUES: usize = 3;
+
+impl Generator<'_> {
+
+    pub(super) fn match_statement(&mut self, subject: &Expression, cases: &[Case]) -> Result<(), String> {
+        let line = subject.line_number;
+        let type_ = self.type_of(subject)?;
+        if !matches!(type_, Type::Bool | Type::Int | Type::Float | Type::String) {
+            return Err(format!("only numbers and strings can be matched, but found {:?} on line {}", type_, line));
+        }
+        for pattern in cases.iter().flat_map(|(patterns, _)| patterns) {
+            let pattern_type = self.type_of(pattern)?;
+            if storage(&pattern_type) != storage(&type_) {
+                return Err(format!("a {:?} pattern can never match {:?} on line {}", pattern_type, type_, pattern.line_number));
+            }
+        }
+        // the subject is only read before any case runs, so one variable serves nested matches too
+        let subject = match subject.type_.as_ref() {
+            ExpressionType::Field(_) => subject.clone(),
+            _ => {
+                let key = format!("{}$match{:?}", self.function, storage(&type_));
+                let code = self.expression(subject)?;
+                let temp = self.declare(&key, &type_)?;
+                self.lines.push(format!("{}→{}", code.text, temp));
+                Expression::new(ExpressionType::Field(key), line)
+            }
+        };
+        if cases.len() >= DISPATCH_CASES {
+            self.dispatch(&subject, cases)
+        } else {
+            self.ladder(&subject, cases)
+        }
+    }
+
+    /// Lowers the cases to nested `If`/`Else` blocks.
+    fn ladder(&mut self, subject: &Expression, cases: &[Case]) -> Result<(), String> {
+        let Some(((patterns, body), rest)) = cases.split_first() else {
+            return Ok(());
+        };
+        if patterns.is_empty() {
+            return self.statements(body);
+        }
+        let code = self.condition(&self.matches(subject, patterns))?;
+        let ((), body) = self.capture(|generator| generator.statements(body))?;
+        let ((), else_body) = self.capture(|generator| generator.ladder(subject, rest))?;
+        let else_body = if else_body.is_empty() { None } else { Some(else_body) };
+        self.block(format!("If {}", code.text), body, else_body);
+        Ok(())
+    }
+
+    /// Lowers the cases to one-line tests that `Goto` the matching case. Each jump stays within the block the
+    /// `match` is in, as leaving an `If`, `While` or `For` block through `Goto` leaks memory.
+    fn dispatch(&mut self, subject: &Expression, cases: &[Case]) -> Result<(), String> {
+        let mut labels = Vec::new();
+        for _ in cases {
+            labels.push(self.label(subject.line_number)?);
+        }
+        let end = self.label(subject.line_number)?;
+        let mut fallback = end.clone();
+        for ((patterns, _), label) in cases.iter().zip(&labels) {
+            if patterns.is_empty() {
+                fallback = label.clone();
+                continue;
+            }
+            let code = self.condition(&self.matches(subject, patterns))?;
+            self.lines.push(format!("If {}", code.text));
+            self.lines.push(format!("Goto {}", label));
+        }
+        self.lines.push(format!("Goto {}", fallback));
+        for (i, ((_, body), label)) in cases.iter().zip(&labels).enumerate() {
+            self.lines.push(format!("Lbl {}", label));
+            self.statements(body)?;
+            if i + 1 < cases.len() {
+                self.lines.push(format!("Goto {}", end));
+            }
+        }
+        self.lines.push(format!("Lbl {}", end));
+        Ok(())
+    }
+
+    /// The condition under which `subject` matches one of `patterns`.
+    fn matches(&self, subject: &Expression, patterns: &[Expression]) -> Expression {
+        let line = subject.line_number;
+        let operation = |operation: Operation| Expression::new(ExpressionType::Operation(operation), line);
+        let mut integers: Vec<i64> = patterns.iter().filter_map(super::integer_literal).collect();
+        integers.sort_unstable();
+        integers.dedup();
+        let mut tests = Vec::new();
+        let mut start = 0;
+        while start < integers.len() {
+            let mut end = start;
+            while end + 1 < integers.len() && integers[end + 1] == integers[end] + 1 {
+                end += 1;
+            }
+            if end + 1 - start >= RANGE_VALUES {
+                let (low, high) = (super::literal(integers[start], line), super::literal(integers[end], line));
+                tests.push(operation(Operation::And(
+                    operation(Operation::GreaterEquals(subject.clone(), low)),
+                    operation(Operation::LessEquals(subject.clone(), high)),
+                )));
+            } else {
+                tests.extend(integers[start..=end].iter().map(|i| operation(Operation::Equals(subject.clone(), super::literal(*i, line)))));
+            }
+            start = end + 1;
+        }
+        tests.extend(patterns.iter().filter(|pattern| super::integer_literal(pattern).is_none())
+            .map(|pattern| operation(Operation::Equals(subject.clone(), pattern.clone()))));
+        tests.into_iter().reduce(|left, right| operation(Operation::Or(left, right))).unwrap()
+    }
+
+    /// A label unique within the program being generated.
+    fn label(&mut self, line: usize) -> Result<String, String> {
+        const CHARACTERS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let count = CHARACTERS.len();
+        if self.labels == count * count {
+            return Err(format!("ran out of TI-Basic labels on line {}", line));
+        }
+        let label = [self.labels / count, self.labels % count].map(|i| CHARACTERS.as_bytes()[i] as char).iter().collect();
+        self.labels += 1;
+        Ok(label)
+    }
+}
@@ -0,0 +1,206 @@
+use crate::statements::{Expression, ExpressionType, Operation};
+use crate::types::Type;
+use super::{Code, Generator, POSTFIX};
+
+/// Rows and columns of a matrix.
+pub(super) type Shape = (usize, usize);
+
+/// Built-in functions that take or build a matrix.
+pub(super) const FUNCTIONS: [&str; 6] = ["Matrix", "det", "inv", "rref", "transpose", "dim"];
+
+impl Generator<'_> {
+
+    /// Whether `operation` is matrix arithmetic rather than arithmetic on numbers or lists.
+    pub(super) fn is_matrix_operation(&self, operation: &Operation) -> Result<bool, String> {
+        Ok(match operation {
+            Operation::MatMul(_, _) => true,
+            Operation::Add(l, r) | Operation::Sub(l, r) | Operation::Mul(l, r) => {
+                self.type_of(l)? == Type::Matrix || self.type_of(r)? == Type::Matrix
+            }
+            Operation::Pow(e, _) | Operation::Negative(e) => self.type_of(e)? == Type::Matrix,
+            _ => false,
+        })
+    }
+
+    /// The shape of the matrix `expression` where constants permit, checking along the way that every operation is
+    /// defined for the shapes involved.
+    pub(super) fn shape(&self, expression: &Expression) -> Result<Option<Shape>, String> {
+        let line = expression.line_number;
+        Ok(match expression.type_.as_ref() {
+            ExpressionType::Field(name) => self.shapes.get(&self.key(name)).copied().flatten(),
+            ExpressionType::Call(name, args) if !self.programs.contains_key(name) => match name.as_str() {
+                "Matrix" => Some(literal_shape(&args[0], line)?),
+                "transpose" => self.shape(&args[0])?.map(|(rows, columns)| (columns, rows)),
+                "inv" => square(self.shape(&args[0])?, "inverted", line)?,
+                "rref" => self.shape(&args[0])?,
+                _ => None,
+            },
+            ExpressionType::Attribute(receiver, name) if name == "T" => self.shape(receiver)?.map(|(rows, columns)| (columns, rows)),
+            ExpressionType::Conditional(_, value, other) => {
+                let (value, other) = (self.shape(value)?, self.shape(other)?);
+                if value == other { value } else { None }
+            }
+            ExpressionType::Operation(operation) => match operation {
+                Operation::Add(l, r) | Operation::Sub(l, r) => {
+                    let (l_type, r_type) = (self.type_of(l)?, self.type_of(r)?);
+                    if l_type != Type::Matrix || r_type != Type::Matrix {
+                        return Err(format!("unsupported operand types for '{}': {:?} and {:?} on line {}", operation, l_type, r_type, line));
+                    }
+                    match (self.shape(l)?, self.shape(r)?) {
+                        (Some(l), Some(r)) if l != r => {
+                            return Err(format!("cannot use '{}' on a {} matrix and a {} matrix on line {}", operation, describe(l), describe(r), line));
+                        }
+                        (l, r) => l.or(r),
+                    }
+                }
+                Operation::Mul(l, r) => {
+                    let (l_type, r_type) = (self.type_of(l)?, self.type_of(r)?);
+                    match (&l_type, &r_type) {
+                        (Type::Matrix, Type::Matrix) => return Err(format!("use '@' to multiply two matrices on line {}", line)),
+                        (Type::Matrix, Type::Int | Type::Float) => self.shape(l)?,
+                        (Type::Int | Type::Float, Type::Matrix) => self.shape(r)?,
+                        _ => return Err(format!("unsupported operand types for '*': {:?} and {:?} on line {}", l_type, r_type, line)),
+                    }
+                }
+                Operation::MatMul(l, r) => {
+                    let (l_type, r_type) = (self.type_of(l)?, self.type_of(r)?);
+                    if l_type != Type::Matrix || r_type != Type::Matrix {
+                        return Err(format!("'@' multiplies two matrices, but found {:?} and {:?} on line {}", l_type, r_type, line));
+                    }
+                    match (self.shape(l)?, self.shape(r)?) {
+                        (Some(l), Some(r)) if l.1 != r.0 => {
+                            return Err(format!("cannot multiply a {} matrix by a {} matrix on line {}", describe(l), describe(r), line));
+                        }
+                        (Some(l), Some(r)) => Some((l.0, r.1)),
+                        _ => None,
+                    }
+                }
+                Operation::Pow(base, exponent) => match super::integer_literal(exponent) {
+                    Some(-1) => square(self.shape(base)?, "inverted", line)?,
+                    Some(exponent) if exponent >= 0 => square(self.shape(base)?, "raised to a power", line)?,
+                    _ => return Err(format!("a matrix can only be raised to a non-negative integer literal or -1 on line {}", line)),
+                },
+                Operation::Negative(e) => self.shape(e)?,
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Remembers the shape assigned to a matrix variable, forgetting it for good once assignments disagree.
+    pub(super) fn record_shape(&mut self, name: &str, shape: Option<Shape>) {
+        let known = self.shapes.entry(self.key(name)).or_insert(shape);
+        if *known != shape {
+            *known = None;
+        }
+    }
+
+    /// Lowers `matrix[row][column]`, checking literal indices against the matrix's shape when it is known.
+    pub(super) fn matrix_element(&mut self, matrix: &Expression, row: &Expression, column: &Expression) -> Result<Code, String> {
+        let line = matrix.line_number;
+        let name = match matrix.type_.as_ref() {
+            ExpressionType::Field(name) => self.lookup(name, line)?.name.clone(),
+            _ => return Err(format!("only matrix variables can be indexed on line {}", line))
+        };
+        let shape = self.shape(matrix)?;
+        let row = self.matrix_index(row, shape.map(|(rows, _)| rows), line)?;
+        let column = self.matrix_index(column, shape.map(|(_, columns)| columns), line)?;
+        Ok(Code::atom(format!("{}({},{})", name, row.text, column.text)))
+    }
+
+    fn matrix_index(&mut self, index: &Expression, extent: Option<usize>, line: usize) -> Result<Code, String> {
+        match (super::integer_literal(index), extent.map(|extent| extent as i64)) {
+            (Some(i), Some(extent)) if i >= extent || i < -extent => {
+                Err(format!("index {} is out of range for a matrix dimension of {} on line {}", i, extent, line))
+            }
+            (Some(i), Some(extent)) => Ok(super::integer(if i < 0 { extent + i + 1 } else { i + 1 })),
+            (Some(i), None) if i < 0 => Err(format!("negative matrix indices need the matrix's shape to be known on line {}", line)),
+            (_, Some(extent)) => self.index(index, &extent.to_string(), line),
+            (_, None) => self.offset(index, 1),
+        }
+    }
+
+    pub(super) fn matrix_builtin(&mut self, name: &str, args: &[Expression]) -> Result<Code, String> {
+        if name == "Matrix" {
+            let mut rows = Vec::new();
+            for row in rows_of(&args[0]).unwrap_or_default() {
+                let mut values = Vec::new();
+                for value in row {
+                    values.push(self.expression(value)?.text);
+                }
+                rows.push(format!("[{}]", values.join(",")));
+            }
+            return Ok(Code::atom(format!("[{}]", rows.join(""))));
+        }
+        let matrix = self.expression(&args[0])?;
+        Ok(match name {
+            "inv" => Code::new(format!("{}⁻¹", matrix.wrap(POSTFIX)), POSTFIX),
+            "transpose" => Code::new(format!("{}ᵀ", matrix.wrap(POSTFIX)), POSTFIX),
+            _ => Code::atom(format!("{}({})", name, matrix.text)),
+        })
+    }
+
+    pub(super) fn matrix_builtin_type(&self, name: &str, args: &[Expression], line: usize) -> Result<Type, String> {
+        super::builtins::arity(name, args, 1, line)?;
+        if name == "Matrix" {
+            literal_shape(&args[0], line)?;
+            for value in rows_of(&args[0]).unwrap_or_default().into_iter().flatten() {
+                let type_ = self.type_of(value)?;
+                if !matches!(type_, Type::Int | Type::Float) {
+                    return Err(format!("matrices can only hold real numbers, but found {:?} on line {}", type_, line));
+                }
+            }
+            return Ok(Type::Matrix);
+        }
+        let type_ = self.type_of(&args[0])?;
+        if type_ != Type::Matrix {
+            return Err(format!("{}() expects a Matrix, but found {:?} on line {}", name, type_, line));
+        }
+        let shape = self.shape(&args[0])?;
+        Ok(match name {
+            "det" => {
+                square(shape, "used with det()", line)?;
+                Type::Float
+            }
+            "inv" => {
+                square(shape, "inverted", line)?;
+                Type::Matrix
+            }
+            "dim" => Type::IntList,
+            _ => Type::Matrix,
+        })
+    }
+}
+
+/// The rows of a nested list literal like `[[1, 2], [3, 4]]`.
+fn rows_of(literal: &Expression) -> Option<Vec<&[Expression]>> {
+    match literal.type_.as_ref() {
+        ExpressionType::ListLiteral(rows) => rows.iter().map(|row| match row.type_.as_ref() {
+            ExpressionType::ListLiteral(values) => Some(values.as_slice()),
+            _ => None
+        }).collect(),
+        _ => None
+    }
+}
+
+fn literal_shape(literal: &Expression, line: usize) -> Result<Shape, String> {
+    let rows = match rows_of(literal) {
+        Some(rows) if !rows.is_empty() && !rows[0].is_empty() => rows,
+        _ => return Err(format!("Matrix() expects a non-empty list of lists like [[1, 2], [3, 4]] on line {}", line))
+    };
+    if rows.iter().any(|row| row.len() != rows[0].len()) {
+        return Err(format!("every row of a matrix must have the same length on line {}", line));
+    }
+    Ok((rows.len(), rows[0].len()))
+}
+
+fn square(shape: Option<Shape>, operation: &str, line: usize) -> Result<Option<Shape>, String> {
+    match shape {
+        Some(shape) if shape.0 != shape.1 => Err(format!("only square matrices can be {}, but found a {} matrix on line {}", operation, describe(shape), line)),
+        shape => Ok(shape),
+    }
+}
+
+fn describe((rows, columns): Shape) -> String {
+    format!("{}x{}", rows, columns)
+}
@@ -0,0 +1,70 @@
+use super::Program;
+
+/// The mode command a program using complex numbers starts with, as operations with real inputs and complex
+/// results like √(⁻1) fail with ERR:NONREAL ANS in Real mode.
+pub(super) const COMPLEX: &str = "a+bi";
+
+/// The calculator's default mode, which stays set after a program ends.
+const REAL: &str = "Real";
+
+/// Switches back to Real mode wherever a program that switched to a+bi mode ends, so the home screen is left as it
+/// was. `Stop` ends every program at once, so it restores Real in any program that may run while a+bi mode is
+/// on. A program using complex numbers switches to a+bi mode again after calling one that restored Real.
+pub(super) fn restore_real(programs: &mut [Program]) {
+    let complex: Vec<bool> = programs.iter().map(|program| program.lines.first().is_some_and(|line| line == COMPLEX)).collect();
+    let calls: Vec<Vec<usize>> = programs.iter().map(|program| {
+        program.lines.iter().filter_map(|line| called(programs, line)).collect()
+    }).collect();
+    // the programs that leave the calculator in Real mode, having restored it themselves or in a program they call
+    let mut resets = complex.clone();
+    // the programs that may run while a+bi mode is on
+    let mut nested = complex.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, called) in calls.iter().enumerate() {
+            if !resets[i] && called.iter().any(|&j| resets[j]) {
+                resets[i] = true;
+                changed = true;
+            }
+            if nested[i] {
+                for &j in called {
+                    changed |= !nested[j];
+                    nested[j] = true;
+                }
+            }
+        }
+    }
+    for i in 0..programs.len() {
+        if !nested[i] {
+            continue;
+        }
+        let mut lines = Vec::new();
+        for line in std::mem::take(&mut programs[i].lines) {
+            let reset = called(programs, &line).is_some_and(|j| resets[j]);
+            if line == "Stop" || complex[i] && line == "Return" {
+                // the exit is the only line under a one-line If, which now needs a block
+                if lines.last().is_some_and(|last: &String| last.starts_with("If ")) {
+                    lines.extend(["Then".to_string(), REAL.to_string(), line, "End".to_string()]);
+                } else {
+                    lines.extend([REAL.to_string(), line]);
+                }
+                continue;
+            }
+            lines.push(line);
+            if complex[i] && reset {
+                lines.push(COMPLEX.to_string());
+            }
+        }
+        if complex[i] {
+            lines.push(REAL.to_string());
+        }
+        programs[i].lines = lines;
+    }
+}
+
+/// The index of the program `line` calls, if it is a call.
+fn called(programs: &[Program], line: &str) -> Option<usize> {
+    let name = line.strip_prefix("prgm")?;
+    programs.iter().position(|program| program.name == name)
+}
@@ -0,0 +1,247 @@
+use crate::statements::{Expression, ExpressionType, Operation};
+use crate::types::{Record, Type};
+use super::{Code, Generator, Storage};
+
+impl<'a> Generator<'a> {
+
+    pub(super) fn record(&self, name: &str) -> Option<&'a Record> {
+        self.records.iter().find(|record| record.name == name)
+    }
+
+    /// Checks that every class named by `type_` is declared.
+    pub(super) fn check_type(&self, type_: &Type) -> Result<(), String> {
+        match type_ {
+            Type::Record(name) | Type::RecordList(name) if self.record(name).is_none() => Err(format!("unknown type '{}'", name)),
+            Type::Callable(parameters, returned) => {
+                parameters.iter().try_for_each(|parameter| self.check_type(parameter))?;
+                self.check_signature(parameters, returned.as_deref())
+            }
+            _ => Ok(())
+        }
+    }
+
+    /// Allocates one list per field of the list of records `name`, which is itself known by the first of them.
+    pub(super) fn declare_records(&mut self, name: &str, record: &str) -> Result<String, String> {
+        let Some(record) = self.record(record) else {
+            return Err(format!("unknown type '{}' for '{}'", record, name));
+        };
+        let mut lists = Vec::new();
+        for field in &record.fields {
+            let type_ = super::list_type(&field.typetype).unwrap();
+            lists.push(self.declare(&format!("{}.{}", name, field.name), &type_)?);
+        }
+        self.variables.insert(self.key(name), super::Variable { name: lists[0].clone(), type_: Type::RecordList(record.name.clone()) });
+        Ok(lists.swap_remove(0))
+    }
+
+    /// The lists holding each field of the list of records `name`.
+    pub(super) fn field_lists(&self, name: &str, line: usize) -> Result<Vec<String>, String> {
+        let Type::RecordList(record) = &self.lookup(name, line)?.type_ else {
+            return Err(format!("'{}' is not a list of records on line {}", name, line));
+        };
+        self.record(record).unwrap().fields.iter()
+            .map(|field| self.lookup(&format!("{}.{}", name, field.name), line).map(|variable| variable.name.clone()))
+            .collect()
+    }
+
+    pub(super) fn construction_type(&self, record: &Record, args: &[Expression], line: usize) -> Result<Type, String> {
+        let args = super::arguments(&record.name, &record.fields, args, line)?;
+        for (arg, field) in args.iter().zip(&record.fields) {
+            let actual = self.type_of(arg)?;
+            if super::widen(&field.typetype, &actual).as_ref() != Some(&field.typetype) {
+                return Err(format!("field '{}' of '{}' expects {:?}, but found {:?} on line {}", field.name, record.name, field.typetype, actual, line));
+            }
+        }
+        Ok(Type::Record(record.name.clone()))
+    }
+
+    /// Lowers each field of the record `value`, reading them from where they are stored when possible.
+    pub(super) fn fields(&mut self, value: &Expression) -> Result<Vec<Code>, String> {
+        let line = value.line_number;
+        match value.type_.as_ref() {
+            ExpressionType::Call(name, args) if self.record(name).is_some() => {
+                let record = self.record(name).unwrap();
+                let args = self.in_order(&record.fields, args)?;
+                super::arguments(name, &record.fields, &args, line)?.iter().map(|arg| self.expression(arg)).collect()
+            }
+            ExpressionType::Operation(Operation::ArrayIndex(list, index)) => self.record_element(list, index),
+            _ => {
+                let type_ = self.type_of(value)?;
+                let Type::Record(record) = &type_ else {
+                    return Err(format!("expected a record, but found {:?} on line {}", type_, line));
+                };
+                let count = self.record(record).unwrap().fields.len();
+                let list = match value.type_.as_ref() {
+                    ExpressionType::Field(name) => self.lookup(name, line)?.name.clone(),
+                    _ => {
+                        let packed = self.expression(value)?;
+                        let temp = self.temp(&type_)?;
+                        self.lines.push(format!("{}→{}", packed.text, temp));
+                        temp
+                    }
+                };
+                Ok((1..=count).map(|i| Code::atom(format!("{}({})", list, i))).collect())
+            }
+        }
+    }
+
+    /// Lowers `records[index]` to an element of each field's list. Each list measures itself for negative indices,
+    /// so the fields stay in step while the lists are updated one after another.
+    fn record_element(&mut self, list: &Expression, index: &Expression) -> Result<Vec<Code>, String> {
+        let line = list.line_number;
+        let ExpressionType::Field(name) = list.type_.as_ref() else {
+            return Err(format!("only lists of records held in variables can be indexed on line {}", line));
+        };
+        let mut elements = Vec::new();
+        for (i, list) in self.field_lists(name, line)?.into_iter().enumerate() {
+            let length = format!("dim({})", list);
+            let index = if i == 0 {
+                self.index(index, &length, line)?
+            } else {
+                self.unchecked(|generator| generator.index(index, &length, line))?
+            };
+            elements.push(Code::atom(format!("{}({})", list, index.text)));
+        }
+        Ok(elements)
+    }
+
+    /// Lowers a record value into a single packed list.
+    pub(super) fn pack(&mut self, value: &Expression) -> Result<Code, String> {
+        let fields = self.fields(value)?;
+        Ok(Code::atom(format!("{{{}}}", fields.into_iter().map(|field| field.text).collect::<Vec<String>>().join(","))))
+    }
+
+    pub(super) fn record_attribute(&mut self, receiver: &Expression, name: &str, line: usize) -> Result<Code, String> {
+        let position = self.field_position(receiver, name, line)?;
+        Ok(self.fields(receiver)?.swap_remove(position))
+    }
+
+    pub(super) fn field_type(&self, record: &str, name: &str, line: usize) -> Result<Type, String> {
+        match self.record(record).unwrap().fields.iter().find(|field| field.name == name) {
+            Some(field) => Ok(field.typetype.clone()),
+            None => Err(format!("'{}' has no field '{}' on line {}", record, name, line))
+        }
+    }
+
+    fn field_position(&self, receiver: &Expression, name: &str, line: usize) -> Result<usize, String> {
+        let Type::Record(record) = self.type_of(receiver)? else { unreachable!() };
+        self.field_type(&record, name, line)?;
+        Ok(self.record(&record).unwrap().fields.iter().position(|field| field.name == name).unwrap())
+    }
+
+    /// Lowers `receiver.name = value` for a record held in a variable or in a list of records.
+    pub(super) fn store_attribute(&mut self, receiver: &Expression, name: &str, value: &Expression) -> Result<(), String> {
+        let line = receiver.line_number;
+        let stored = match receiver.type_.as_ref() {
+            ExpressionType::Field(_) => true,
+            ExpressionType::Operation(Operation::ArrayIndex(list, _)) => matches!(list.type_.as_ref(), ExpressionType::Field(_)),
+            _ => false,
+        };
+        if !stored {
+            return Err(format!("cannot assign to a field of a temporary record on line {}", line));
+        }
+        let position = self.field_position(receiver, name, line)?;
+        let code = self.expression(value)?;
+        let field = self.fields(receiver)?.swap_remove(position);
+        self.lines.push(format!("{}→{}", code.text, field.text));
+        Ok(())
+    }
+
+    /// Lowers `records[index] = value` field by field.
+    pub(super) fn store_record(&mut self, list: &Expression, index: &Expression, value: &Expression) -> Result<(), String> {
+        let values = self.fields(value)?;
+        let elements = self.record_element(list, index)?;
+        for (value, element) in values.into_iter().zip(elements) {
+            self.lines.push(format!("{}→{}", value.text, element.text));
+        }
+        Ok(())
+    }
+
+    /// Assigns a list literal of records, or another list of records, to the list of records `name`.
+    pub(super) fn assign_records(&mut self, name: &str, type_: &Type, value: &Expression) -> Result<(), String> {
+        let line = value.line_number;
+        let actual = self.type_of(value)?;
+        let empty = matches!(value.type_.as_ref(), ExpressionType::ListLiteral(items) if items.is_empty());
+        if !empty && actual != *type_ {
+            return Err(format!("cannot assign {:?} to a variable declared as {:?} on line {}", actual, type_, line));
+        }
+        let Type::RecordList(record) = type_ else { unreachable!() };
+        self.declare(name, type_)?;
+        let lists = self.field_lists(name, line)?;
+        match value.type_.as_ref() {
+            // TI-Basic has no empty list literal, so the lists are emptied in place
+            _ if empty => {
+                for list in lists {
+                    self.lines.push(format!("0→dim({})", list));
+                }
+            }
+            ExpressionType::ListLiteral(items) => {
+                let mut columns = vec![Vec::new(); lists.len()];
+                for item in items {
+                    for (column, field) in columns.iter_mut().zip(self.fields(item)?) {
+                        column.push(field.text);
+                    }
+                }
+                for (list, column) in lists.into_iter().zip(columns) {
+                    self.lines.push(format!("{{{}}}→{}", column.join(","), list));
+                }
+            }
+            ExpressionType::Field(other) => {
+                for (list, other) in lists.into_iter().zip(self.field_lists(other, line)?) {
+                    if list != other {
+                        self.lines.push(format!("{}→{}", other, list));
+                    }
+                }
+            }
+            _ => return Err(format!("a list of '{}' can only be assigned a list literal or another list on line {}", record, line))
+        }
+        Ok(())
+    }
+
+    /// Lowers a method that changes a list of records in place.
+    pub(super) fn records_method(&mut self, name: &str, record: &str, method: &str, args: &[Expression], line: usize) -> Result<(), String> {
+        let lists = self.field_lists(name, line)?;
+        match method {
+            "append" => {
+                super::builtins::arity(method, args, 1, line)?;
+                let actual = self.type_of(&args[0])?;
+                if actual != Type::Record(record.to_string()) {
+                    return Err(format!("cannot append {:?} to a list of '{}' on line {}", actual, record, line));
+                }
+                // storing one past the end grows a list by one element
+                for (value, list) in self.fields(&args[0])?.into_iter().zip(lists) {
+                    self.lines.push(format!("{}→{}(dim({})+1)", value.text, list, list));
+                }
+            }
+            "clear" => {
+                super::builtins::arity(method, args, 0, line)?;
+                for list in lists {
+                    self.lines.push(format!("0→dim({})", list));
+                }
+            }
+            _ => return Err(format!("a list of '{}' has no method '{}' on line {}", record, method, line))
+        }
+        Ok(())
+    }
+
+    /// Copies a record into the loop variable of `for variable in records`, at the loop's `index`.
+    pub(super) fn record_iteration(&self, list: &str, variable: &str, index: &str, line: usize) -> Result<String, String> {
+        let fields = self.field_lists(list, line)?.into_iter().map(|list| format!("{}({})", list, index)).collect::<Vec<String>>();
+        Ok(format!("{{{}}}→{}", fields.join(","), variable))
+    }
+}
+
+/// Checks the classes before any code is generated, as their fields decide how records are stored.
+pub(super) fn check_records(records: &[Record], functions: &[crate::types::Function]) -> Result<(), String> {
+    for (i, record) in records.iter().enumerate() {
+        if records[..i].iter().any(|other| other.name == record.name) || functions.iter().any(|function| function.name == record.name) {
+            return Err(format!("'{}' is declared more than once", record.name));
+        }
+        for field in &record.fields {
+            if super::storage(&field.typetype) != Storage::Real {
+                return Err(format!("field '{}' of class '{}' must be a bool, int, float or Complex, but found {:?}", field.name, record.name, field.typetype));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet};
+use crate::statements::{Expression, ExpressionType, Statement, StatementType};
+use crate::types::Function;
+use super::Generator;
+
+/// Which names each function binds itself and which it declares `global`. TI-Basic variables are all global, so
+/// locals are told apart by prefixing them with their function, as in `main::score`. There are only 27 real
+/// variables, so functions that are never running at the same time share the variables of their locals.
+pub(super) struct Scopes {
+    locals: HashMap<String, HashSet<String>>,
+    globals: HashMap<String, HashSet<String>>,
+    calls: HashMap<String, HashSet<String>>, // every function that may run while a function is running
+}
+
+impl Scopes {
+
+    pub(super) fn resolve(functions: &[Function]) -> Result<Scopes, String> {
+        let mut scopes = Scopes { locals: HashMap::new(), globals: HashMap::new(), calls: calls(functions) };
+        for function in functions {
+            let mut scope = Scope { used: HashSet::new(), globals: HashSet::new() };
+            scope.statements(&function.statements)?;
+            for global in &scope.globals {
+                if function.parameters.iter().any(|parameter| parameter.name == *global) {
+                    return Err(format!("'{}' is a parameter of '{}' and cannot be declared global", global, function.name));
+                }
+            }
+            let mut locals = HashSet::new();
+            bindings(&function.statements, &mut locals);
+            locals.extend(function.parameters.iter().map(|parameter| parameter.name.clone()));
+            locals.retain(|name| !scope.globals.contains(name));
+            scopes.locals.insert(function.name.clone(), locals);
+            scopes.globals.insert(function.name.clone(), scope.globals);
+        }
+        Ok(scopes)
+    }
+
+    /// Whether `name` in `function` is the global of that name, as Python decides it: when declared `global`, or when
+    /// the function never binds it but another function declares it global.
+    fn is_global(&self, function: &str, name: &str) -> bool {
+        if self.globals.get(function).is_some_and(|globals| globals.contains(name)) {
+            return true;
+        }
+        !self.locals.get(function).is_some_and(|locals| locals.contains(name))
+            && self.globals.values().any(|globals| globals.contains(name))
+    }
+
+    /// Whether the locals of the functions owning the keys `key` and `other` can be held in the same variable, as
+    /// neither function can run while the other one is. Globals and Crunch's own shared variables have no owner.
+    pub(super) fn can_share(&self, key: &str, other: &str) -> bool {
+        let (Some(function), Some(other)) = (owner(key), owner(other)) else {
+            return false;
+        };
+        function != other
+            && !self.calls.get(function).is_some_and(|called| called.contains(other))
+            && !self.calls.get(other).is_some_and(|called| called.contains(function))
+    }
+}
+
+impl Generator<'_> {
+
+    /// Declares each global from the first assignment to it whose type is already known, so that functions can read
+    /// globals that only a later function assigns.
+    pub(super) fn declare_globals(&mut self) -> Result<(), String> {
+        let functions = self.functions;
+        for function in functions {
+            self.function = function.name.clone();
+            self.global_assignments(&function.statements)?;
+        }
+        self.function = String::new();
+        Ok(())
+    }
+
+    fn global_assignments(&mut self, statements: &[Statement]) -> Result<(), String> {
+        for statement in statements {
+            match statement.type_.as_ref() {
+                StatementType::Assignment(target, value) | StatementType::Declaration(target, _, value) => {
+                    let ExpressionType::Field(name) = target.type_.as_ref() else { continue };
+                    if !self.scopes.is_global(&self.function, name) || self.variables.contains_key(name) {
+                        continue;
+                    }
+                    let type_ = match statement.type_.as_ref() {
+                        StatementType::Declaration(_, type_, _) => type_.clone(),
+                        // values reading locals cannot be typed before their function is generated
+                        _ => match self.type_of(value) {
+                            Ok(type_) => type_,
+                            Err(_) => continue,
+                        },
+                    };
+                    self.declare(name, &type_)?;
+                }
+                StatementType::If(_, body, else_body) => {
+                    self.global_assignments(body)?;
+                    self.global_assignments(else_body.as_slice())?;
+                }
+                StatementType::While(_, body) | StatementType::For(_, _, body) => self.global_assignments(body)?,
+                StatementType::Match(_, cases) => cases.iter().try_for_each(|(_, body)| self.global_assignments(body))?,
+                StatementType::Try(body, handlers, otherwise, finally) => {
+                    [body, otherwise, finally].into_iter().try_for_each(|block| self.global_assignments(block))?;
+                    handlers.iter().try_for_each(|(_, handler)| self.global_assignments(handler))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The key of the variable `name` refers to in the current function. Compiler-made names containing `$` are
+    /// already unique, and keys that were resolved before are returned as they are.
+    pub(super) fn key(&self, name: &str) -> String {
+        if name.contains('$') || name.contains("::") {
+            return name.to_string();
+        }
+        // the lists of a list of records are keyed under the list's own name
+        let base = name.split('.').next().unwrap();
+        if self.scopes.is_global(&self.function, base) {
+            name.to_string()
+        } else {
+            local(&self.function, name)
+        }
+    }
+}
+
+/// The key of the local `name` in `function`.
+pub(super) fn local(function: &str, name: &str) -> String {
+    format!("{}::{}", function, name)
+}
+
+/// The function a key belongs to: the one a local, or a temporary named after its function, was made for.
+fn owner(key: &str) -> Option<&str> {
+    match key.split_once("::") {
+        Some((function, _)) => Some(function),
+        None => key.split_once('$').map(|(function, _)| function).filter(|function| !function.is_empty()),
+    }
+}
+
+/// The functions each function may run, directly or through the functions it calls. A call through a `Callable`
+/// may run any function that is referenced anywhere, or any lambda, whose body reads the locals of the function it
+/// is written in.
+fn calls(functions: &[Function]) -> HashMap<String, HashSet<String>> {
+    struct Visit<'a> {
+        functions: &'a [Function],
+        variables: HashSet<String>,
+        referenced: HashSet<String>,
+        called: HashSet<String>,
+        indirect: bool,
+        lambdas: bool,
+    }
+    impl Visit<'_> {
+        fn expression(&mut self, expression: &Expression) {
+            let defined = |name: &String| self.functions.iter().any(|function| function.name == *name);
+            match expression.type_.as_ref() {
+                ExpressionType::Call(name, _) if defined(name) => { self.called.insert(name.clone()); }
+                // calling a variable goes through a Callable
+                ExpressionType::Call(name, _) => self.indirect |= self.variables.contains(name),
+                ExpressionType::Field(name) if defined(name) => { self.referenced.insert(name.clone()); }
+                ExpressionType::Lambda(_, body) => {
+                    self.lambdas = true;
+                    self.expression(body);
+                }
+                _ => {}
+            }
+            super::children(expression).into_iter().for_each(|child| self.expression(child));
+        }
+
+        fn statements(&mut self, statements: &[Statement]) {
+            for statement in statements {
+                super::expressions(statement).into_iter().for_each(|expression| self.expression(expression));
+                super::nested(statement).into_iter().for_each(|block| self.statements(block));
+            }
+        }
+    }
+    let mut variables = HashSet::new();
+    for function in functions {
+        bindings(&function.statements, &mut variables);
+        variables.extend(function.parameters.iter().map(|parameter| parameter.name.clone()));
+    }
+    let mut visit = Visit { functions, variables, referenced: HashSet::new(), called: HashSet::new(), indirect: false, lambdas: false };
+    let mut direct = HashMap::new();
+    let mut indirect = HashSet::new();
+    let mut lambdas = HashSet::new();
+    for function in functions {
+        visit.statements(&function.statements);
+        if std::mem::take(&mut visit.indirect) {
+            indirect.insert(function.name.clone());
+        }
+        if std::mem::take(&mut visit.lambdas) {
+            lambdas.insert(function.name.clone());
+        }
+        direct.insert(function.name.clone(), std::mem::take(&mut visit.called));
+    }
+    let referenced = visit.referenced;
+    for function in &indirect {
+        direct.get_mut(function).unwrap().extend(referenced.iter().chain(&lambdas).cloned());
+    }
+    let mut calls = HashMap::new();
+    for function in functions {
+        let mut reached = HashSet::new();
+        let mut pending: Vec<&String> = direct[&function.name].iter().collect();
+        while let Some(next) = pending.pop() {
+            if reached.insert(next.clone()) {
+                pending.extend(&direct[next]);
+            }
+        }
+        calls.insert(function.name.clone(), reached);
+    }
+    calls
+}
+
+/// Tracks the names a function has mentioned so far, as `global` must come before any use of the name.
+struct Scope {
+    used: HashSet<String>,
+    globals: HashSet<String>,
+}
+
+impl Scope {
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), String> {
+        for statement in statements {
+            match statement.type_.as_ref() {
+                StatementType::Global(names) => {
+                    for name in names {
+                        if self.used.contains(name) {
+                            return Err(format!("'{}' is used before its global declaration on line {}", name, statement.line_number));
+                        }
+                        self.globals.insert(name.clone());
+                    }
+                }
+                StatementType::If(_, body, else_body) => {
+                    super::expressions(statement).into_iter().for_each(|expression| self.expression(expression));
+                    self.statements(body)?;
+                    self.statements(else_body.as_slice())?;
+                }
+                StatementType::While(_, body) => {
+                    super::expressions(statement).into_iter().for_each(|expression| self.expression(expression));
+                    self.statements(body)?;
+                }
+                StatementType::Match(_, cases) => {
+                    super::expressions(statement).into_iter().for_each(|expression| self.expression(expression));
+                    cases.iter().try_for_each(|(_, body)| self.statements(body))?;
+                }
+                StatementType::Try(body, handlers, otherwise, finally) => {
+                    self.statements(body)?;
+                    handlers.iter().try_for_each(|(_, handler)| self.statements(handler))?;
+                    self.statements(otherwise)?;
+                    self.statements(finally)?;
+                }
+                StatementType::For(variable, list, body) => {
+                    self.used.insert(variable.clone());
+                    self.used.insert(list.clone());
+                    self.statements(body)?;
+                }
+                _ => super::expressions(statement).into_iter().for_each(|expression| self.expression(expression)),
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression.type_.as_ref() {
+            ExpressionType::Field(name) => { self.used.insert(name.clone()); }
+            ExpressionType::Comprehension(_, variable, _) => { self.used.insert(variable.clone()); }
+            _ => {}
+        }
+        super::children(expression).into_iter().for_each(|child| self.expression(child));
+    }
+}
+
+/// Collects the names `statements` assign to, which Python makes local to the function.
+fn bindings(statements: &[Statement], names: &mut HashSet<String>) {
+    for statement in statements {
+        match statement.type_.as_ref() {
+            StatementType::Assignment(target, _) | StatementType::Declaration(target, _, _) => targets(target, names),
+            StatementType::If(_, body, else_body) => {
+                bindings(body, names);
+                bindings(else_body.as_slice(), names);
+            }
+            StatementType::While(_, body) => bindings(body, names),
+            StatementType::Match(_, cases) => cases.iter().for_each(|(_, body)| bindings(body, names)),
+            StatementType::Try(body, handlers, otherwise, finally) => {
+                [body, otherwise, finally].into_iter().for_each(|block| bindings(block, names));
+                handlers.iter().for_each(|(_, handler)| bindings(handler, names));
+            }
+            StatementType::For(variable, _, body) => {
+                names.insert(variable.clone());
+                bindings(body, names);
+            }
+            _ => {}
+        }
+        super::expressions(statement).into_iter().for_each(|expression| comprehensions(expression, names));
+    }
+}
+
+fn targets(target: &Expression, names: &mut HashSet<String>) {
+    match target.type_.as_ref() {
+        ExpressionType::Field(name) => { names.insert(name.clone()); }
+        ExpressionType::Tuple(items) => items.iter().for_each(|item| targets(item, names)),
+        _ => {}
+    }
+}
+
+/// Comprehension variables belong to the function too, as they are held in its variables.
+fn comprehensions(expression: &Expression, names: &mut HashSet<String>) {
+    if let ExpressionType::Comprehension(_, variable, _) = expression.type_.as_ref() {
+        names.insert(variable.clone());
+    }
+    super::children(expression).into_iter().for_each(|child| comprehensions(child, names));
+}
@@ -0,0 +1,671 @@
+use super::{Options, Program};
+
+/// Compiles `source` as the file `test.py`.
+pub(super) fn compile(source: &str, debug: bool) -> Result<Vec<Program>, String> {
+    let options = Options { comments: false, debug };
+    crate::transpile(&["test.py"], vec![source.to_string()], &options)
+}
+
+/// The lines of the program `name` compiled from `source`.
+pub(super) fn program(source: &str, name: &str) -> Vec<String> {
+    lines(compile(source, false), name)
+}
+
+/// Like `program`, for a debug build.
+pub(super) fn debug_program(source: &str, name: &str) -> Vec<String> {
+    lines(compile(source, true), name)
+}
+
+/// Like `program`, keeping the comments.
+pub(super) fn commented_program(source: &str, name: &str) -> Vec<String> {
+    let options = Options { comments: true, debug: false };
+    lines(crate::transpile(&["test.py"], vec![source.to_string()], &options), name)
+}
+
+/// The error compiling `source` fails with.
+pub(super) fn error(source: &str) -> String {
+    match compile(source, false) {
+        Ok(_) => panic!("expected an error compiling:\n{}", source),
+        Err(error) => error,
+    }
+}
+
+fn lines(programs: Result<Vec<Program>, String>, name: &str) -> Vec<String> {
+    let programs = programs.unwrap_or_else(|error| panic!("{}", error));
+    match programs.into_iter().find(|program| program.name == name) {
+        Some(program) => program.lines,
+        None => panic!("no program named {}", name),
+    }
+}
+
+#[test]
+fn slice_bounds_count_back_and_clamp() {
+    let source = "def main(a: list[int], n: int):\n    print(a[n:])\n    print(a[0:100])\n    print(a[-3:])\n    print(a[::-1])\n";
+    assert_eq!(program(source, "MAIN"), [
+        "Disp seq(ʟA(B),B,max(1,A+(A<0)dim(ʟA)+1),dim(ʟA))",
+        "Disp seq(ʟA(B),B,1,min(100,dim(ʟA)))",
+        "Disp seq(ʟA(B),B,max(1,dim(ʟA)-2),dim(ʟA))",
+        "Disp seq(ʟA(B),B,dim(ʟA),1,⁻1)",
+    ]);
+}
+
+#[test]
+fn slice_of_string_folds_its_length() {
+    let source = "def main(s: str):\n    print(s[1:3])\n    print(s[:4])\n";
+    assert_eq!(program(source, "MAIN"), ["Disp sub(Str1,2,min(3,length(Str1))-1)", "Disp sub(Str1,1,min(4,length(Str1)))"]);
+}
+
+#[test]
+fn slice_evaluates_computed_bounds_once() {
+    let source = "def main(a: list[int], n: int):\n    print(a[:n + 1])\n";
+    assert_eq!(program(source, "MAIN"), ["A+1→B", "Disp seq(ʟA(C),C,1,min(B+(B<0)dim(ʟA),dim(ʟA)))"]);
+}
+
+#[test]
+fn slice_that_is_always_empty_is_rejected() {
+    for slice in ["a[5:2]", "a[-1:-3]", "a[:0]", "a[2:5:-1]", "s[3:1]"] {
+        let source = format!("def main(a: list[int], s: str):\n    print({})\n", slice);
+        assert!(error(&source).contains("always empty"), "{}", slice);
+    }
+}
+
+#[test]
+fn slice_past_a_literal_is_rejected() {
+    assert!(error("def main():\n    print(\"abc\"[0:10])\n").contains("out of range"));
+}
+
+#[test]
+fn while_true_runs_forever() {
+    let source = "def main():\n    while True:\n        print(1)\n";
+    assert_eq!(program(source, "MAIN"), ["While 1", "Disp 1", "End"]);
+}
+
+#[test]
+fn while_false_is_dropped() {
+    let source = "def main():\n    while 0 > 1:\n        print(1)\n    print(2)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp 2"]);
+}
+
+#[test]
+fn while_known_to_run_once_tests_at_end() {
+    let source = "def main(n: int):\n    i = 0\n    while i < n:\n        i += 1\n";
+    assert_eq!(program(source, "MAIN"), ["0→B", "While B<A", "B+1→B", "End"]);
+    let source = "def main():\n    i = 0\n    while i < 10:\n        i += 1\n";
+    assert_eq!(program(source, "MAIN"), ["0→A", "Repeat A≥10", "A+1→A", "End"]);
+}
+
+#[test]
+fn trailing_break_becomes_repeat() {
+    let source = "def main(n: int):\n    i = 0\n    while True:\n        i += 1\n        if i > n:\n            break\n";
+    assert_eq!(program(source, "MAIN"), ["0→B", "Repeat B>A", "B+1→B", "End"]);
+}
+
+#[test]
+fn break_sets_a_flag_the_loop_tests() {
+    let source = "def main(a: list[int], n: int):\n    i = 0\n    while i < n:\n        if a[i] == 0:\n            break\n        i += 1\n";
+    assert_eq!(program(source, "MAIN"), [
+        "0→B", "0→C", "While not(C) and B<A", "If ʟA(B+(B<0)dim(ʟA)+1)=0", "1→C", "If not(C)", "B+1→B", "End",
+    ]);
+}
+
+#[test]
+fn continue_skips_the_rest_of_the_pass() {
+    let source = "def main(n: int):\n    while n > 0:\n        n -= 1\n        if n == 3:\n            continue\n        print(n)\n";
+    assert_eq!(program(source, "MAIN"), ["While A>0", "0→B", "A-1→A", "If A=3", "1→B", "If not(B)", "Disp A", "End"]);
+}
+
+#[test]
+fn break_in_for_moves_index_to_end() {
+    let source = "def main(a: list[int]):\n    for x in a:\n        if x > 5:\n            break\n        print(x)\n";
+    assert_eq!(program(source, "MAIN"), [
+        "0→C", "For(B,1,dim(ʟA))", "ʟA(B)→A", "If A>5", "Then", "1→C", "dim(ʟA)→B", "End", "If not(C)", "Disp A", "End",
+    ]);
+}
+
+#[test]
+fn break_outside_loop_is_rejected() {
+    assert!(error("def main():\n    break\n").contains("'break' outside loop"));
+}
+
+#[test]
+fn get_key_reads_a_new_key_each_time() {
+    let source = "def main():\n    while getKey() == 0:\n        pass\n    k = getKey()\n    print(k + getKey())\n";
+    assert_eq!(program(source, "MAIN"), ["While getKey=0", "End", "getKey→A", "Disp A+getKey"]);
+}
+
+#[test]
+fn get_key_in_a_chained_comparison_is_read_once() {
+    let source = "def main():\n    print(20 < getKey() < 30)\n";
+    assert_eq!(program(source, "MAIN"), ["0→A", "getKey→B", "If 20<B", "B<30→A", "Disp A"]);
+}
+
+#[test]
+fn match_reads_get_key_once_before_its_cases() {
+    let ladder = "def main():\n    match getKey():\n        case 24:\n            print(1)\n        case 26:\n            print(2)\n";
+    assert_eq!(program(ladder, "MAIN"), ["getKey→A", "If A=24", "Then", "Disp 1", "Else", "If A=26", "Disp 2", "End"]);
+    let mut dispatch = "def main():\n    match getKey():\n".to_string();
+    for key in [21, 24, 25, 34, 45] {
+        dispatch += &format!("        case {}:\n            print({})\n", key, key);
+    }
+    let lines = program(&dispatch, "MAIN");
+    assert_eq!(lines[..3], ["getKey→A", "If A=21", "Goto 00"]);
+    assert_eq!(lines.iter().filter(|line| line.contains("getKey")).count(), 1);
+}
+
+#[test]
+fn constants_fold_inside_lambdas() {
+    let source = "from typing import Callable\n\nSIZE = 2 * 4\n\ndef main(n: int):\n    f: Callable[[int], int] = lambda x: x + SIZE * 2\n    print(f(n))\n";
+    assert_eq!(program(source, "CRCALL1"), ["B+16"]);
+}
+
+#[test]
+fn complex_program_restores_real_before_every_exit() {
+    let source = "def root(x: float) -> Complex:\n    if x > 0:\n        return Complex(x)\n    return Complex(0, x)\n";
+    assert_eq!(program(source, "ROOT"), ["a+bi", "If A>0", "Then", "A", "Real", "Return", "End", "0+Ai", "Real"]);
+    let source = "def root(x: float) -> Complex:\n    if x > 0:\n        return Complex(0, x)\n    print(x)\n    return Complex(x)\n";
+    assert_eq!(program(source, "ROOT")[..6], ["a+bi", "If A>0", "Then", "0+Ai", "Real", "Return"]);
+}
+
+#[test]
+fn complex_caller_switches_back_after_a_call() {
+    let source = "def root(x: float) -> Complex:\n    return Complex(0, x)\n\ndef check(n: int):\n    assert n > 0\n\n\
+        def main(n: float):\n    z = root(n)\n    check(1)\n    print(z)\n";
+    assert_eq!(debug_program(source, "MAIN"), ["a+bi", "B→A", "prgmROOT", "a+bi", "Ans→C", "1→A", "prgmCHECK", "Disp C", "Real"]);
+    // a failed assert stops every program at once, so CHECK restores Real for MAIN
+    assert_eq!(debug_program(source, "CHECK"), ["If A≤0", "Then", "Disp \"AssertionError\",\"test.py LINE 5\"", "Real", "Stop", "End"]);
+}
+
+#[test]
+fn real_program_keeps_its_mode() {
+    let source = "def main(n: int):\n    if n > 0:\n        return\n    print(n)\n";
+    assert_eq!(program(source, "MAIN"), ["If A>0", "Return", "Disp A"]);
+}
+
+#[test]
+fn and_guards_an_operand_that_could_error() {
+    let source = "def main(a: list[int], i: int):\n    if i < len(a) and a[i] > 0:\n        print(1)\n";
+    assert_eq!(program(source, "MAIN"), ["If A<dim(ʟA)", "Then", "If ʟA(A+(A<0)dim(ʟA)+1)>0", "Disp 1", "End"]);
+}
+
+#[test]
+fn and_of_safe_operands_stays_one_condition() {
+    let source = "def main(x: int):\n    if x > 0 and x < 10:\n        print(2)\n";
+    assert_eq!(program(source, "MAIN"), ["If A>0 and A<10", "Disp 2"]);
+}
+
+#[test]
+fn or_only_evaluates_its_right_side_when_the_left_is_false() {
+    let source = "def main(a: list[int], i: int, x: int):\n    print(x > 0 or a[i] > 0)\n";
+    assert_eq!(program(source, "MAIN"), ["1→C", "If not(B>0)", "ʟA(A+(A<0)dim(ʟA)+1)>0→C", "Disp C"]);
+}
+
+#[test]
+fn shifts_and_inversion_are_arithmetic() {
+    let source = "def main(x: int, y: int):\n    print(~x)\n    print(x << 3)\n    print(x >> y)\n";
+    assert_eq!(program(source, "MAIN"), [
+        "Disp ⁻A-1", "Disp A*8", "If B<0", "Then", "Disp \"ValueError\",\"test.py LINE 4\"", "Stop", "End", "Disp int(A/2^B)",
+    ]);
+}
+
+#[test]
+fn negative_literal_shift_is_rejected() {
+    assert!(error("def main(x: int):\n    print(x << -1)\n").contains("negative shift count on line 2"));
+}
+
+#[test]
+fn bitwise_and_calls_a_digit_loop_helper() {
+    let source = "def main(x: int, y: int):\n    print(x & y)\n";
+    assert_eq!(program(source, "MAIN"), ["A→C", "B→D", "prgmCRAND", "Ans→G", "Disp G"]);
+    assert_eq!(program(source, "CRAND"), [
+        "0→E", "1→F", "While C(C+1) or D(D+1)", "E+F*(fPart(C/2) and fPart(D/2))→E", "int(C/2)→C", "int(D/2)→D", "2*F→F", "End",
+        "E-F*(C and D)",
+    ]);
+}
+
+#[test]
+fn division_operators_follow_python() {
+    let source = "def main(x: int, y: int):\n    print(x // y)\n    print(x % y)\n    print(x / y)\n    print(x ** 2)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp int(A/B)", "Disp A-B*int(A/B)", "Disp A/B", "Disp A²"]);
+}
+
+#[test]
+fn floor_division_of_literals_rounds_down() {
+    let source = "def main():\n    print(-7 // 2)\n    print(2 ** -1)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp ⁻4", "Disp .5"]);
+}
+
+#[test]
+fn chained_comparisons_test_each_pair() {
+    let source = "def main(x: int, y: int):\n    print(0 < x < 10)\n    print(0 < x <= y < 10)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp 0<A and A<10", "Disp 0<A and A≤B and B<10"]);
+}
+
+#[test]
+fn conditional_expression_of_safe_values_is_arithmetic() {
+    let source = "def main(x: int, y: int):\n    print(1 if x > y else 2)\n    print(x if x > 0 else 0)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp (A>B)+2*not(A>B)", "Disp A*(A>0)"]);
+}
+
+#[test]
+fn conditional_expression_that_could_error_branches() {
+    let source = "def main(a: list[int], x: int):\n    print(a[0] if x > 0 else 5)\n";
+    assert_eq!(program(source, "MAIN"), ["If A>0", "Then", "ʟA(1)→B", "Else", "5→B", "End", "Disp B"]);
+}
+
+#[test]
+fn string_literals_take_python_quotes_and_escapes() {
+    let source = "def main():\n    print('it\\'s')\n    print(\"\"\"two\nlines\"\"\")\n";
+    assert_eq!(program(source, "MAIN"), ["Disp \"it's\"", "Disp \"two\",\"lines\""]);
+}
+
+#[test]
+fn import_text_inside_a_string_is_kept() {
+    let source = "from typing import Final\n\ndef main():\n    print(\"\"\"a\nfrom typing import b\"\"\")\n";
+    assert_eq!(program(source, "MAIN"), ["Disp \"a\",\"from typing import b\""]);
+}
+
+#[test]
+fn other_imports_are_rejected() {
+    assert!(error("import math\n\ndef main():\n    print(1)\n").contains("import statements are not supported on line 1"));
+}
+
+#[test]
+fn string_with_a_control_character_is_rejected() {
+    assert!(error("def main():\n    print(\"a\\tb\")\n").contains("TI-Basic strings cannot hold"));
+}
+
+#[test]
+fn string_operations_map_to_ti_functions() {
+    let source = "def main(s: str):\n    print(s + \"x\")\n    print(len(s))\n    print(\"a\" in s)\n    print(s.find(\"ab\"))\n";
+    assert_eq!(program(source, "MAIN"), [
+        "Disp Str1+\"x\"", "Disp length(Str1)", "Disp inString(Str1,\"a\")≠0", "Disp inString(Str1,\"ab\")-1",
+    ]);
+}
+
+#[test]
+fn f_string_concatenates_its_parts() {
+    let source = "def main(s: str):\n    print(f\"<{s}>\")\n";
+    assert_eq!(program(source, "MAIN"), ["Disp \"<\"+Str1+\">\""]);
+}
+
+#[test]
+fn str_of_a_number_calls_the_conversion_helper() {
+    let source = "def main(n: int):\n    print(\"n=\" + str(n))\n";
+    assert_eq!(program(source, "MAIN"), ["A→B", "prgmCRSTR", "Ans→Str2", "Disp \"n=\"+Str2"]);
+    assert_eq!(program(source, "CRSTR"), ["{0,1}→L₁", "{0,B}→L₂", "LinReg(ax+b) Y₁", "Equ►String(Y₁,Str1)", "sub(Str1,1,length(Str1)-3)"]);
+}
+
+#[test]
+fn str_of_literals_and_bools_needs_no_helper() {
+    let source = "def main(b: bool):\n    print(str(42))\n    print(str(b))\n";
+    assert_eq!(program(source, "MAIN"), ["Disp \"42\"", "Disp sub(\"FalseTrue\",1+5A,5-A)"]);
+}
+
+#[test]
+fn int_and_float_of_a_string_evaluate_it() {
+    let source = "def main(s: str, f: float):\n    print(int(s))\n    print(float(s))\n    print(int(f))\n";
+    assert_eq!(program(source, "MAIN"), ["Disp expr(Str1)", "Disp expr(Str1)", "Disp iPart(A)"]);
+}
+
+#[test]
+fn integer_literals_in_any_base_are_decimal() {
+    let source = "def main():\n    print(0xFF)\n    print(0b101)\n    print(0o17)\n    print(1_000_000)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp 255", "Disp 5", "Disp 15", "Disp 1000000"]);
+}
+
+#[test]
+fn scientific_literals_use_the_exponent_token() {
+    let source = "def main():\n    print(1.5e3)\n    print(2e-5)\n    print(1e20)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp 1500", "Disp 2ᴇ⁻5", "Disp 1ᴇ20"]);
+}
+
+#[test]
+fn imaginary_literal_uses_i() {
+    let source = "def main():\n    print(3j)\n";
+    assert_eq!(program(source, "MAIN"), ["a+bi", "Disp 3i", "Real"]);
+}
+
+#[test]
+fn comments_are_kept_only_when_asked() {
+    let source = "def main(n: int):\n    # count down\n    print(n)  # shown\n";
+    assert_eq!(commented_program(source, "MAIN"), ["\"count down", "Disp A"]);
+    assert_eq!(program(source, "MAIN"), ["Disp A"]);
+}
+
+#[test]
+fn var_directive_pins_a_register() {
+    let source = "def main(n: int):\n    # crunch: var=Z\n    i = n\n    print(i)\n";
+    assert_eq!(program(source, "MAIN"), ["A→Z", "Disp Z"]);
+}
+
+#[test]
+fn list_literal_and_methods() {
+    let source = "def main(a: list[int], n: int):\n    b = [1, 2, 3]\n    b.append(n)\n    b.extend(a)\n    print(b)\n";
+    assert_eq!(program(source, "MAIN"), ["{1,2,3}→ʟB", "A→ʟB(dim(ʟB)+1)", "augment(ʟB,ʟA)→ʟB", "Disp ʟB"]);
+}
+
+#[test]
+fn comprehensions_lower_to_seq() {
+    let source = "def main(a: list[int], n: int):\n    print([x * 2 for x in a])\n    print([i * i for i in range(n)])\n";
+    assert_eq!(program(source, "MAIN"), ["Disp seq(ʟA(C)*2,C,1,dim(ʟA))", "Disp seq(D*D,D,0,A-1)"]);
+}
+
+#[test]
+fn list_builtins_map_to_ti_functions() {
+    let source = "def main(a: list[int]):\n    print(len(a))\n    print(sum(a))\n    print(max(a))\n    print(sorted(a))\n";
+    assert_eq!(program(source, "MAIN"), ["Disp dim(ʟA)", "Disp sum(ʟA)", "Disp max(ʟA)", "ʟA→ʟMAINT", "SortA(ʟMAINT)", "Disp ʟMAINT"]);
+}
+
+#[test]
+fn indices_shift_to_one_based() {
+    let source = "def main(a: list[int], i: int, s: str):\n    print(a[0])\n    print(a[i + 1])\n    a[2] = 5\n    print(s[i])\n";
+    assert_eq!(program(source, "MAIN"), [
+        "Disp ʟA(1)", "A+1→B", "Disp ʟA(B+(B<0)dim(ʟA)+1)", "5→ʟA(3)", "Disp sub(Str1,A+(A<0)length(Str1)+1,1)",
+    ]);
+}
+
+#[test]
+fn negative_literal_index_counts_from_the_end() {
+    let source = "def main(a: list[int]):\n    print(a[-1])\n    print(a[-2])\n";
+    assert_eq!(program(source, "MAIN"), ["Disp ʟA(dim(ʟA))", "Disp ʟA(dim(ʟA)-1)"]);
+}
+
+#[test]
+fn runtime_index_counts_back_when_negative() {
+    let source = "def main(a: list[int], n: int):\n    i: int = -1\n    print(a[i])\n    print(a[n % 3])\n";
+    assert_eq!(program(source, "MAIN"), ["⁻1→B", "Disp ʟA(B+(B<0)dim(ʟA)+1)", "Disp ʟA(A-3*int(A/3)+1)"]);
+}
+
+#[test]
+fn debug_build_checks_indices() {
+    let source = "def main(a: list[int], i: int):\n    print(a[i])\n";
+    assert_eq!(debug_program(source, "MAIN"), [
+        "If A+(A<0)dim(ʟA)+1<1 or A+(A<0)dim(ʟA)+1>dim(ʟA)", "Then", "Disp \"IndexError\",\"test.py LINE 2\"", "Stop", "End",
+        "Disp ʟA(A+(A<0)dim(ʟA)+1)",
+    ]);
+}
+
+#[test]
+fn slice_with_a_step_passes_it_to_seq() {
+    let source = "def main(a: list[int]):\n    print(a[1:3])\n    print(a[::2])\n";
+    assert_eq!(program(source, "MAIN"), ["Disp seq(ʟA(A),A,2,min(3,dim(ʟA)))", "Disp seq(ʟA(A),A,1,dim(ʟA),2)"]);
+}
+
+#[test]
+fn matrix_literals_and_operations_use_matrix_tokens() {
+    let source = "def main(m: Matrix, n: Matrix):\n    a = Matrix([[1, 2], [3, 4]])\n    print(a @ m)\n    print(m + n)\n    print(transpose(m))\n    print(m * 2)\n";
+    assert_eq!(program(source, "MAIN"), ["[[1,2][3,4]]→[C]", "Disp [C]*[A]", "Disp [A]+[B]", "Disp [A]ᵀ", "Disp [A]*2"]);
+}
+
+#[test]
+fn matrix_functions_and_indexing() {
+    let source = "def main(m: Matrix):\n    print(det(m))\n    print(inv(m))\n    print(rref(m))\n    print(m[1, 0])\n    print(m[0][1])\n";
+    assert_eq!(program(source, "MAIN"), ["Disp det([A])", "Disp [A]⁻¹", "Disp rref([A])", "Disp [A](2,1)", "Disp [A](1,2)"]);
+}
+
+#[test]
+fn matrix_shapes_are_checked_when_known() {
+    let source = "def main():\n    a = Matrix([[1, 2], [3, 4]])\n    b = Matrix([[1, 2, 3]])\n    print(a @ b)\n";
+    assert!(error(source).contains("cannot multiply a 2x2 matrix by a 1x3 matrix"));
+    let source = "def main():\n    b = Matrix([[1, 2, 3]])\n    print(det(b))\n";
+    assert!(error(source).contains("only square matrices"));
+}
+
+#[test]
+fn complex_parts_and_construction() {
+    let source = "def main(z: Complex, x: float):\n    print(z.real)\n    print(z.imag)\n    print(z.conjugate())\n    print(Complex(x, -2) * z)\n";
+    assert_eq!(program(source, "MAIN"), ["a+bi", "Disp real(A)", "Disp imag(A)", "Disp conj(A)", "Disp (B-2i)*A", "Real"]);
+}
+
+#[test]
+fn record_instance_is_a_packed_list() {
+    let source = "from dataclasses import dataclass\n\n@dataclass\nclass Point:\n    x: int\n    y: int = 0\n\ndef main(n: int):\n    p = Point(n, 2)\n    print(p.x + p.y)\n";
+    assert_eq!(program(source, "MAIN"), ["{A,2}→ʟP", "Disp ʟP(1)+ʟP(2)"]);
+}
+
+#[test]
+fn list_of_records_is_a_list_per_field() {
+    let source = "@dataclass\nclass Point:\n    x: int\n    y: int = 0\n\ndef main():\n    ps = [Point(1, 2), Point(3)]\n    for q in ps:\n        print(q.y)\n    print(ps[1].x)\n";
+    assert_eq!(program(source, "MAIN"), ["{1,3}→ʟX", "{2,0}→ʟY", "For(A,1,dim(ʟX))", "{ʟX(A),ʟY(A)}→ʟQ", "Disp ʟQ(2)", "End", "Disp ʟX(2)"]);
+}
+
+#[test]
+fn unknown_record_field_is_rejected() {
+    let source = "@dataclass\nclass Point:\n    x: int\n\ndef main():\n    p = Point(1)\n    print(p.z)\n";
+    assert!(error(source).contains("'z'"));
+}
+
+#[test]
+fn tuple_return_goes_through_return_variables() {
+    let source = "def divmod2(a: int, b: int) -> tuple[int, int]:\n    return a // b, a % b\n\ndef main(n: int):\n    q, r = divmod2(n, 3)\n    print(q)\n    print(r)\n";
+    assert_eq!(program(source, "DIVMOD2"), ["int(A/B)→C", "A-B*int(A/B)→D"]);
+    assert_eq!(program(source, "MAIN"), ["E→A", "3→B", "prgmDIVMOD2", "C→F", "D→G", "Disp F", "Disp G"]);
+}
+
+#[test]
+fn swap_evaluates_every_value_first() {
+    let source = "def main():\n    x, y = 1, 2\n    x, y = y, x\n    print(x)\n";
+    assert_eq!(program(source, "MAIN"), ["1→A", "2→B", "A→C", "B→A", "C→B", "Disp A"]);
+}
+
+#[test]
+fn unpacking_the_wrong_count_is_rejected() {
+    assert!(error("def main():\n    x, y = 1, 2, 3\n").contains("cannot unpack 3 values into 2 targets"));
+}
+
+#[test]
+fn defaults_and_keywords_fill_the_parameters() {
+    let source = "def area(w: int, h: int = 2) -> int:\n    return w * h\n\ndef main(n: int):\n    print(area(n))\n    print(area(h=3, w=n))\n";
+    assert_eq!(program(source, "AREA"), ["A*B"]);
+    assert_eq!(program(source, "MAIN"), ["C→A", "2→B", "prgmAREA", "Ans→D", "Disp D", "C→A", "3→B", "prgmAREA", "Ans→D", "Disp D"]);
+}
+
+#[test]
+fn keyword_arguments_are_evaluated_in_source_order() {
+    let source = "def g(a: int, b: int):\n    print(a - b)\n\ndef main(n: int):\n    g(b=getKey(), a=getKey())\n    g(b=n + 1, a=n)\n";
+    assert_eq!(program(source, "MAIN"), ["getKey→D", "getKey→E", "E→A", "D→B", "prgmG", "C→A", "C+1→B", "prgmG"]);
+}
+
+#[test]
+fn default_must_be_a_constant() {
+    let source = "def area(w: int, h: int = w) -> int:\n    return w * h\n";
+    assert!(error(source).contains("must be a constant"));
+}
+
+#[test]
+fn missing_and_unknown_arguments_are_rejected() {
+    let source = "def area(w: int, h: int = 2) -> int:\n    return w * h\n\ndef main():\n    print(area(h=3))\n";
+    assert!(error(source).contains("'w'"));
+    let source = "def area(w: int, h: int = 2) -> int:\n    return w * h\n\ndef main():\n    print(area(1, d=3))\n";
+    assert!(error(source).contains("'d'"));
+}
+
+#[test]
+fn bools_are_zero_or_one() {
+    let source = "def main(n: int, b: bool):\n    done: bool = n > 3\n    if done:\n        print(1)\n    if not b:\n        print(2)\n    print(bool(n))\n    print(True)\n";
+    assert_eq!(program(source, "MAIN"), ["A>3→C", "If C", "Disp 1", "If not(B)", "Disp 2", "Disp A≠0", "Disp 1"]);
+}
+
+#[test]
+fn bool_return_type() {
+    let source = "def even(n: int) -> bool:\n    return n % 2 == 0\n";
+    assert_eq!(program(source, "EVEN"), ["A-2*int(A/2)=0"]);
+}
+
+#[test]
+fn condition_must_be_real() {
+    assert!(error("def main(s: str):\n    if s:\n        print(1)\n").contains("a condition must be a bool or a real number"));
+}
+
+#[test]
+fn pass_leaves_nothing() {
+    let source = "def main(i: int):\n    if i > 0:\n        pass\n    print(i)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp A"]);
+}
+
+#[test]
+fn del_removes_a_list_element() {
+    let source = "def main(a: list[int], i: int):\n    del a[i]\n    del a[0]\n    del a[-1]\n";
+    assert_eq!(program(source, "MAIN"), [
+        "For(B,A+(A<0)dim(ʟA)+1,dim(ʟA)-1)", "ʟA(B+1)→ʟA(B)", "End", "dim(ʟA)-1→dim(ʟA)",
+        "For(B,1,dim(ʟA)-1)", "ʟA(B+1)→ʟA(B)", "End", "dim(ʟA)-1→dim(ʟA)",
+        "dim(ʟA)-1→dim(ʟA)",
+    ]);
+}
+
+#[test]
+fn del_from_a_one_element_list_moves_nothing() {
+    let source = "def main():\n    a = [5]\n    del a[0]\n    print(len(a))\n";
+    assert_eq!(program(source, "MAIN"), ["{5}→ʟA", "For(A,1,dim(ʟA)-1)", "ʟA(A+1)→ʟA(A)", "End", "dim(ʟA)-1→dim(ʟA)", "Disp dim(ʟA)"]);
+}
+
+#[test]
+fn in_searches_lists_and_strings() {
+    let source = "def main(a: list[int], s: str, i: int):\n    print(3 in a)\n    print(\"x\" in s)\n    print(i not in a)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp max(ʟA=3)", "Disp inString(Str1,\"x\")≠0", "Disp not(max(ʟA=A))"]);
+}
+
+#[test]
+fn assert_stops_with_the_python_line_in_debug_builds() {
+    let source = "def main(n: int):\n    assert n > 0, \"n must be positive\"\n    assert n\n    print(n)\n";
+    assert_eq!(debug_program(source, "MAIN"), [
+        "If A≤0", "Then", "Disp \"AssertionError\",\"n must be positive\",\"test.py LINE 2\"", "Stop", "End",
+        "If not(A)", "Then", "Disp \"AssertionError\",\"test.py LINE 3\"", "Stop", "End",
+        "Disp A",
+    ]);
+}
+
+#[test]
+fn assert_is_stripped_from_release_builds() {
+    let source = "def main(n: int):\n    assert n > 0, \"n must be positive\"\n    print(n)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp A"]);
+}
+
+#[test]
+fn global_is_shared_and_locals_are_not() {
+    let source = "def reset():\n    global score\n    score = 0\n\ndef add(n: int):\n    global score\n    score += n\n\n\
+        def local(n: int):\n    score = n\n    print(score)\n\ndef main(n: int):\n    reset()\n    add(n)\n    local(2)\n    print(score)\n";
+    assert_eq!(program(source, "RESET"), ["0→A"]);
+    assert_eq!(program(source, "ADD"), ["A+B→A"]);
+    assert_eq!(program(source, "LOCAL"), ["B→C", "Disp C"]);
+    assert_eq!(program(source, "MAIN"), ["prgmRESET", "D→B", "prgmADD", "2→B", "prgmLOCAL", "Disp A"]);
+}
+
+#[test]
+fn nonlocal_has_nothing_to_refer_to() {
+    assert!(error("def main():\n    nonlocal x\n").contains("no binding for nonlocal 'x'"));
+}
+
+#[test]
+fn match_nests_a_branch_per_case() {
+    let source = "def main(n: int):\n    match n:\n        case 1 | 2:\n            print(1)\n        case 3:\n            print(3)\n        case _:\n            print(0)\n";
+    assert_eq!(program(source, "MAIN"), ["If A=1 or A=2", "Then", "Disp 1", "Else", "If A=3", "Then", "Disp 3", "Else", "Disp 0", "End", "End"]);
+}
+
+#[test]
+fn match_on_strings_and_ranges() {
+    let source = "def main(n: int, s: str):\n    match s:\n        case \"a\":\n            print(1)\n    match n:\n        case 1 | 2 | 3 | 4:\n            print(9)\n";
+    assert_eq!(program(source, "MAIN"), ["If Str1=\"a\"", "Disp 1", "If A≥1 and A≤4", "Disp 9"]);
+}
+
+#[test]
+fn match_pattern_of_the_wrong_type_is_rejected() {
+    let source = "def main(n: int):\n    match n:\n        case \"a\":\n            print(1)\n";
+    assert!(error(source).contains("can never match"));
+}
+
+#[test]
+fn try_with_one_handler_checks_before_the_block() {
+    let source = "def main(x: int):\n    try:\n        print(10 / x)\n    except ZeroDivisionError:\n        print(0)\n";
+    assert_eq!(program(source, "MAIN"), ["If A≠0", "Then", "Disp 10/A", "Else", "Disp 0", "End"]);
+}
+
+#[test]
+fn try_with_several_handlers_sets_a_failure_flag() {
+    let source = "def main(a: list[int], x: int, i: int):\n    try:\n        print(a[i] / x)\n    except ZeroDivisionError:\n        print(1)\n\
+        \x20   except IndexError:\n        print(2)\n    finally:\n        print(3)\n";
+    assert_eq!(program(source, "MAIN"), [
+        "0→C", "If not(B≥⁻dim(ʟA) and B<dim(ʟA))", "2→C", "If C=0 and A=0", "1→C",
+        "If C=0", "Then", "Disp ʟA(B+(B<0)dim(ʟA)+1)/A", "Else", "If C=1", "Then", "Disp 1", "Else", "If C=2", "Disp 2", "End", "End",
+        "Disp 3",
+    ]);
+}
+
+#[test]
+fn try_around_a_changed_operand_is_rejected() {
+    let source = "def main(x: int):\n    try:\n        x -= 1\n        print(10 / x)\n    except ZeroDivisionError:\n        print(0)\n";
+    assert!(error(source).contains("changes inside the try block"));
+}
+
+#[test]
+fn try_stores_a_computed_operand_once() {
+    let source = "def main(x: int, a: list[int], i: int):\n    try:\n        print(10 // (x - 1))\n        print(a[i + 1])\n\
+        \x20   except Exception:\n        print(0)\n";
+    assert_eq!(program(source, "MAIN"), [
+        "A-1→C", "B+1→D", "If C≠0 and (D≥⁻dim(ʟA) and D<dim(ʟA))", "Then", "Disp int(10/C)", "Disp ʟA(D+(D<0)dim(ʟA)+1)",
+        "Else", "Disp 0", "End",
+    ]);
+}
+
+#[test]
+fn try_around_an_operand_with_side_effects_is_rejected() {
+    let source = "def f() -> int:\n    return 2\n\ndef main():\n    try:\n        x = 10 // f()\n    except ZeroDivisionError:\n        x = 0\n";
+    assert!(error(source).contains("an operand with side effects cannot be checked"));
+}
+
+#[test]
+fn try_after_a_call_is_rejected() {
+    let source = "def f():\n    print(1)\n\ndef main(x: int):\n    try:\n        f()\n        print(10 / x)\n    except ZeroDivisionError:\n        print(0)\n";
+    assert!(error(source).contains("'x' may be changed by the call on line 6"));
+}
+
+#[test]
+fn functions_never_running_together_share_variables() {
+    let source = "def first(n: int):\n    x = n * 2\n    print(x)\n\ndef second(n: int):\n    y = n + 1\n    print(y)\n\n\
+        def main():\n    first(1)\n    second(2)\n";
+    assert_eq!(program(source, "FIRST"), ["A*2→B", "Disp B"]);
+    assert_eq!(program(source, "SECOND"), ["A+1→B", "Disp B"]);
+    assert_eq!(program(source, "MAIN"), ["1→A", "prgmFIRST", "2→A", "prgmSECOND"]);
+}
+
+#[test]
+fn running_out_of_real_variables_is_reported() {
+    let source: String = std::iter::once("def main():\n".to_string()).chain((0..28).map(|i| format!("    v{} = {}\n    print(v{})\n", i, i, i))).collect();
+    assert!(error(&source).contains("ran out of TI-Basic variables for Int while allocating 'v27' in 'main'"));
+}
+
+#[test]
+fn callables_are_ids_run_by_a_dispatch_program() {
+    let source = "from typing import Callable\n\ndef double(x: int) -> int:\n    return 2 * x\n\n\
+        def apply(f: Callable[[int], int], v: int) -> int:\n    return f(v)\n\n\
+        def main(n: int):\n    print(apply(double, n))\n    print(apply(lambda x: x + 1, n))\n";
+    assert_eq!(program(source, "APPLY"), ["C→D", "B→E", "prgmCRCALL1"]);
+    assert_eq!(program(source, "MAIN"), ["1→B", "F→C", "prgmAPPLY", "Ans→G", "Disp G", "4→B", "F→C", "prgmAPPLY", "Ans→G", "Disp G"]);
+    assert_eq!(program(source, "CRCALL1"), ["If E=1", "Then", "D→A", "prgmDOUBLE", "Return", "End", "D+1"]);
+}
+
+#[test]
+fn callable_of_the_wrong_signature_is_rejected() {
+    let source = "from typing import Callable\n\ndef apply(f: Callable[[int], int], v: int) -> int:\n    return f(v)\n\n\
+        def main(n: int):\n    print(apply(lambda x, y: x + y, n))\n";
+    assert!(error(source).contains("the lambda takes 2 parameters"));
+}
+
+#[test]
+fn constants_are_inlined_and_folded() {
+    let source = "from typing import Final\n\nWIDTH = 8\nSCALE: Final = WIDTH * 2 + 1\nDEBUG = False\nNAME = \"a\" + \"b\"\n\n\
+        def main(n: int):\n    print(n * SCALE)\n    print(1 / 3)\n    print(3 * 4 - 2)\n    if DEBUG:\n        print(0)\n    print(NAME)\n";
+    assert_eq!(program(source, "MAIN"), ["Disp A*17", "Disp 1/3", "Disp 10", "Disp \"ab\""]);
+}
+
+#[test]
+fn constants_cannot_be_assigned() {
+    let source = "WIDTH = 8\n\ndef main():\n    WIDTH = 9\n";
+    assert!(error(source).contains("cannot assign to constant 'WIDTH'"));
+}
+
+#[test]
+fn constant_must_be_known_at_compile_time() {
+    let source = "def f() -> int:\n    return 1\n\nWIDTH = f()\n";
+    assert!(error(source).contains("known at compile time"));
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use crate::codegen::children_mut;
+use crate::statements::{Expression, ExpressionType, Operation, Statement, StatementType};
+use crate::types::{Constant, Function, Record, Type};
+
+/// TI-Basic keeps 14 significant digits, so folded results are rounded to as many.
+const PRECISION: usize = 14;
+
+/// Folded results that aren't whole numbers are only kept up to this many digits, as a longer literal takes more
+/// room than the operation it replaces, like `1/3` does.
+const FLOAT_DIGITS: usize = 6;
+
+/// A value known at compile time.
+enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+/// Substitutes the constants wherever they are read and folds every operation on values known at compile time.
+pub(crate) fn fold(functions: &mut [Function], records: &mut [Record], constants: Vec<Constant>) -> Result<(), String> {
+    let mut values = HashMap::new();
+    for mut constant in constants {
+        let line = constant.value.line_number;
+        if values.contains_key(&constant.name) {
+            return Err(format!("constant '{}' is assigned twice on line {}", constant.name, line));
+        }
+        if functions.iter().any(|function| function.name == constant.name) {
+            return Err(format!("constant '{}' has the name of a function on line {}", constant.name, line));
+        }
+        Folder { constants: &values }.expression(&mut constant.value)?;
+        let Some(value) = evaluate(&constant.value) else {
+            return Err(format!("constant '{}' must be a number, bool or string known at compile time on line {}", constant.name, line));
+        };
+        if let Some(declared) = &constant.typetype {
+            let actual = match value {
+                Value::Int(_) => Type::Int,
+                Value::Float(_) => Type::Float,
+                Value::Bool(_) => Type::Bool,
+                Value::String(_) => Type::String,
+            };
+            if *declared != actual && !matches!((declared, &actual), (Type::Float, Type::Int | Type::Bool) | (Type::Int, Type::Bool)) {
+                return Err(format!("constant '{}' is declared as {:?}, but found {:?} on line {}", constant.name, declared, actual, line));
+            }
+        }
+        values.insert(constant.name, constant.value);
+    }
+    let folder = Folder { constants: &values };
+    for record in records {
+        for field in &mut record.fields {
+            if let Some(default) = &mut field.default {
+                folder.expression(default)?;
+            }
+        }
+    }
+    for function in functions {
+        for parameter in &mut function.parameters {
+            if values.contains_key(&parameter.name) {
+                return Err(format!("parameter '{}' of '{}' has the name of a constant", parameter.name, function.name));
+            }
+            if let Some(default) = &mut parameter.default {
+                folder.expression(default)?;
+            }
+        }
+        folder.statements(&mut function.statements)?;
+    }
+    Ok(())
+}
+
+struct Folder<'a> {
+    constants: &'a HashMap<String, Expression>,
+}
+
+impl Folder<'_> {
+
+    fn statements(&self, statements: &mut [Statement]) -> Result<(), String> {
+        for statement in statements {
+            let line = statement.line_number;
+            match statement.type_.as_mut() {
+                StatementType::Assignment(target, value) | StatementType::Declaration(target, _, value) => {
+                    self.target(target)?;
+                    self.expression(value)?;
+                }
+                StatementType::If(condition, body, else_body) => {
+                    self.expression(condition)?;
+                    self.statements(body)?;
+                    self.statements(else_body.as_mut_slice())?;
+                }
+                StatementType::While(condition, body) => {
+                    self.expression(condition)?;
+                    self.statements(body)?;
+                }
+                StatementType::For(variable, _, body) => {
+                    self.bind(variable, line)?;
+                    self.statements(body)?;
+                }
+                StatementType::Return(value) => {
+                    if let Some(value) = value {
+                        self.expression(value)?;
+                    }
+                }
+                StatementType::Ignored(expression) => self.expression(expression)?,
+                StatementType::Del(target) => self.target(target)?,
+                StatementType::Assert(condition, message) => {
+                    self.expression(condition)?;
+                    if let Some(message) = message {
+                        self.expression(message)?;
+                    }
+                }
+                StatementType::Global(names) => names.iter().try_for_each(|name| self.bind(name, line))?,
+                StatementType::Match(subject, cases) => {
+                    self.expression(subject)?;
+                    for (patterns, body) in cases {
+                        patterns.iter_mut().try_for_each(|pattern| self.expression(pattern))?;
+                        self.statements(body)?;
+                    }
+                }
+                StatementType::Try(body, handlers, otherwise, finally) => {
+                    self.statements(body)?;
+                    handlers.iter_mut().try_for_each(|(_, handler)| self.statements(handler))?;
+                    self.statements(otherwise)?;
+                    self.statements(finally)?;
+                }
+                StatementType::Comment(_) | StatementType::Pass | StatementType::Break | StatementType::Continue => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Folds the operations in `expression`, innermost first, after substituting the constants it reads.
+    fn expression(&self, expression: &mut Expression) -> Result<(), String> {
+        let line = expression.line_number;
+        if let ExpressionType::Operation(Operation::Increment(target) | Operation::Decrement(target)) = expression.type_.as_mut() {
+            return self.target(target);
+        }
+        match expression.type_.as_ref() {
+            ExpressionType::Field(name) => {
+                if let Some(value) = self.constants.get(name) {
+                    *expression = value.clone();
+                    relocate(expression, line);
+                }
+                return Ok(());
+            }
+            ExpressionType::Comprehension(_, variable, _) => self.bind(variable, line)?,
+            ExpressionType::Lambda(parameters, _) => parameters.iter().try_for_each(|parameter| self.bind(parameter, line))?,
+            _ => {}
+        }
+        for child in children_mut(expression) {
+            self.expression(child)?;
+        }
+        if let ExpressionType::Lambda(_, body) = expression.type_.as_mut() {
+            self.expression(body)?;
+        }
+        if let ExpressionType::Operation(_) = expression.type_.as_ref() {
+            if let Some(value) = evaluate(expression).filter(is_short) {
+                *expression = literal(value, line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Folds an assigned `target`, which must not be a constant.
+    fn target(&self, target: &mut Expression) -> Result<(), String> {
+        match target.type_.as_mut() {
+            ExpressionType::Field(name) => self.bind(name, target.line_number),
+            ExpressionType::Tuple(targets) => targets.iter_mut().try_for_each(|target| self.target(target)),
+            _ => self.expression(target),
+        }
+    }
+
+    fn bind(&self, name: &str, line: usize) -> Result<(), String> {
+        if self.constants.contains_key(name) {
+            return Err(format!("cannot assign to constant '{}' on line {}", name, line));
+        }
+        Ok(())
+    }
+}
+
+/// The value of `expression` if it only operates on literals, computed as the calculator would. Operations that would
+/// fail, like dividing by zero, are left for the calculator to report.
+fn evaluate(expression: &Expression) -> Option<Value> {
+    let operation = match expression.type_.as_ref() {
+        ExpressionType::IntLiteral(i) => return Some(Value::Int(*i)),
+        ExpressionType::FloatLiteral(f) => return Some(Value::Float(*f)),
+        ExpressionType::BoolLiteral(b) => return Some(Value::Bool(*b)),
+        ExpressionType::StringLiteral(s) => return Some(Value::String(s.clone())),
+        ExpressionType::Operation(operation) => operation,
+        _ => return None,
+    };
+    let both = |l: &Expression, r: &Expression| Some((evaluate(l)?, evaluate(r)?));
+    let compare = |l: &Expression, r: &Expression, test: fn(f64, f64) -> bool| {
+        let (l, r) = both(l, r)?;
+        Some(Value::Bool(test(number(&l)?, number(&r)?)))
+    };
+    let value = match operation {
+        Operation::Negative(e) => match evaluate(e)? {
+            Value::Float(f) => Value::Float(-f),
+            value => Value::Int(integer(&value)?.checked_neg()?),
+        },
+        Operation::Not(e) => Value::Bool(number(&evaluate(e)?)? == 0.0),
+        Operation::BinaryNot(e) => Value::Int(!integer(&evaluate(e)?)?),
+        Operation::Add(l, r) => match both(l, r)? {
+            (Value::String(l), Value::String(r)) => Value::String(l + &r),
+            (l, r) => arithmetic(&l, &r, i64::checked_add, |l, r| l + r)?,
+        },
+        Operation::Sub(l, r) => {
+            let (l, r) = both(l, r)?;
+            arithmetic(&l, &r, i64::checked_sub, |l, r| l - r)?
+        }
+        Operation::Mul(l, r) => {
+            let (l, r) = both(l, r)?;
+            arithmetic(&l, &r, i64::checked_mul, |l, r| l * r)?
+        }
+        Operation::Div(l, r) => {
+            let (l, r) = both(l, r)?;
+            let (l, r) = (number(&l)?, number(&r)?);
+            if r == 0.0 {
+                return None;
+            }
+            Value::Float(l / r)
+        }
+        // Python rounds toward negative infinity and gives the remainder the sign of the divisor
+        Operation::FloorDiv(l, r) => {
+            let (l, r) = both(l, r)?;
+            if number(&r)? == 0.0 {
+                return None;
+            }
+            arithmetic(&l, &r, |l, r| Some(l.div_euclid(r) - i64::from(r < 0 && l.rem_euclid(r) != 0)), |l, r| (l / r).floor())?
+        }
+        Operation::Mod(l, r) => {
+            let (l, r) = both(l, r)?;
+            if number(&r)? == 0.0 {
+                return None;
+            }
+            arithmetic(&l, &r, |l, r| Some((l % r + r) % r), |l, r| l - r * (l / r).floor())?
+        }
+        Operation::Pow(l, r) => {
+            let (l, r) = both(l, r)?;
+            match (integer(&l), integer(&r)) {
+                (Some(l), Some(r)) if r >= 0 => Value::Int(l.checked_pow(u32::try_from(r).ok()?)?),
+                _ => Value::Float(number(&l)?.powf(number(&r)?)),
+            }
+        }
+        Operation::LBS(l, r) => {
+            let (l, r) = both(l, r)?;
+            let shift = u32::try_from(integer(&r)?).ok().filter(|shift| *shift < 63)?;
+            Value::Int(integer(&l)?.checked_mul(1 << shift)?)
+        }
+        Operation::RBS(l, r) => {
+            let (l, r) = both(l, r)?;
+            let shift = u32::try_from(integer(&r)?).ok().filter(|shift| *shift < 63)?;
+            Value::Int(integer(&l)? >> shift)
+        }
+        Operation::BinaryAnd(l, r) => {
+            let (l, r) = both(l, r)?;
+            Value::Int(integer(&l)? & integer(&r)?)
+        }
+        Operation::BinaryOr(l, r) => {
+            let (l, r) = both(l, r)?;
+            Value::Int(integer(&l)? | integer(&r)?)
+        }
+        Operation::BinaryXor(l, r) => {
+            let (l, r) = both(l, r)?;
+            Value::Int(integer(&l)? ^ integer(&r)?)
+        }
+        Operation::Equals(l, r) | Operation::NotEqual(l, r) => {
+            let equal = match both(l, r)? {
+                (Value::String(l), Value::String(r)) => l == r,
+                (l, r) => number(&l)? == number(&r)?,
+            };
+            Value::Bool(equal == matches!(operation, Operation::Equals(_, _)))
+        }
+        Operation::LessThan(l, r) => compare(l, r, |l, r| l < r)?,
+        Operation::GreaterThan(l, r) => compare(l, r, |l, r| l > r)?,
+        Operation::LessEquals(l, r) => compare(l, r, |l, r| l <= r)?,
+        Operation::GreaterEquals(l, r) => compare(l, r, |l, r| l >= r)?,
+        // `False and x` and `True or x` never evaluate `x`, so it needn't be known
+        Operation::And(l, r) | Operation::Or(l, r) => match (evaluate(l)?, matches!(operation, Operation::And(_, _))) {
+            (Value::Bool(l), and) if l != and => Value::Bool(l),
+            (Value::Bool(_), _) => match evaluate(r)? {
+                Value::Bool(r) => Value::Bool(r),
+                _ => return None,
+            },
+            _ => return None,
+        },
+        _ => return None,
+    };
+    precise(value)
+}
+
+/// Applies `int` when both operands are whole, and `float` otherwise.
+fn arithmetic(l: &Value, r: &Value, int: fn(i64, i64) -> Option<i64>, float: fn(f64, f64) -> f64) -> Option<Value> {
+    match (integer(l), integer(r)) {
+        (Some(l), Some(r)) => int(l, r).map(Value::Int),
+        _ => Some(Value::Float(float(number(l)?, number(r)?))),
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int(i) => Some(*i),
+        Value::Bool(b) => Some(i64::from(*b)),
+        _ => None,
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float(f) => Some(*f),
+        Value::String(_) => None,
+        value => integer(value).map(|i| i as f64),
+    }
+}
+
+/// Rounds `value` to the calculator's precision, or `None` if the calculator couldn't hold it exactly or at all.
+fn precise(value: Value) -> Option<Value> {
+    match value {
+        Value::Int(i) if i.unsigned_abs() >= 10u64.pow(PRECISION as u32) => None,
+        Value::Float(0.0) => Some(Value::Float(0.0)),
+        Value::Float(f) if !f.is_finite() || f.abs() >= 1e100 || f.abs() < 1e-99 => None,
+        Value::Float(f) => format!("{:.*e}", PRECISION - 1, f).parse().ok().map(Value::Float),
+        value => Some(value),
+    }
+}
+
+fn is_short(value: &Value) -> bool {
+    match value {
+        Value::Float(f) if f.fract() != 0.0 => {
+            let text = format!("{:e}", f);
+            let mantissa = text.split('e').next().unwrap();
+            mantissa.chars().filter(char::is_ascii_digit).count() <= FLOAT_DIGITS
+        }
+        _ => true,
+    }
+}
+
+fn literal(value: Value, line: usize) -> Expression {
+    let (type_, negative) = match value {
+        Value::Int(i) => (ExpressionType::IntLiteral(i.abs()), i < 0),
+        Value::Float(f) => (ExpressionType::FloatLiteral(f.abs()), f < 0.0),
+        Value::Bool(b) => (ExpressionType::BoolLiteral(b), false),
+        Value::String(s) => (ExpressionType::StringLiteral(s), false),
+    };
+    let literal = Expression::new(type_, line);
+    if negative {
+        Expression::new(ExpressionType::Operation(Operation::Negative(literal)), line)
+    } else {
+        literal
+    }
+}
+
+/// Gives a substituted constant the line it is read on, so errors point there.
+fn relocate(expression: &mut Expression, line: usize) {
+    expression.line_number = line;
+    for child in children_mut(expression) {
+        relocate(child, line);
+    }
+}
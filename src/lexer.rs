@@ -14,9 +14,7 @@ impl ToTokens for String {
         let mut maybe_cur: Option<char>;
         let mut next: Option<char> = Some(chars.remove(0));
         let collected: &mut Vec<char> = &mut Vec::new();
-        let mut string_state = false;
-        let mut ignore_text = false;
-        let mut commenting = false;
+        let mut comment: Option<String> = None;
         let mut line_number = 1;
         let mut space_count = 0;
         let mut counting_spaces = true;
@@ -29,37 +27,19 @@ impl ToTokens for String {
             }
             if maybe_cur == None { break }
             let cur = maybe_cur.unwrap();
-            if cur == '\\' {
-                if next == Some('"') || next == Some('\'') { ignore_text = true; }
-            }
-            if cur == '"' {
-                if !ignore_text {
-                    if !string_state {
-                        string_state = true;
-                        continue;
-                    } else {
-                        let finalized = collected.iter().collect::<String>();
-                        tokens.push(TokenEntry { token: Token::StringLiteral(finalized), line_number });
-                        collected.clear();
-                        string_state = false;
-                        continue;
-                    }
-                } else {
-                    ignore_text = false;
+            if let Some(text) = comment.as_mut() {
+                if cur != '\n' {
+                    if cur != '\r' { text.push(cur); }
+                    continue;
                 }
+                push_comment(comment.take().unwrap_or_default(), counting_spaces, space_count, &mut tokens, line_number);
             }
-            if string_state {
-                collected.push(cur);
+            if cur == '#' {
+                push_collected(&mut tokens, collected, line_number)?;
+                comment = Some(String::new());
                 continue;
             }
-            if cur == '\r' || cur == '\n' {
-                commenting = false;
-            }
-            if cur == '#' && next == Some('#') {commenting = true; }
             let token_ref = &mut tokens;
-            if commenting {
-                continue;
-            }
             if cur == ' ' {
                 space_count += 1;
             } else if cur != '\n' {
@@ -71,6 +51,25 @@ impl ToTokens for String {
             } else {
                 space_count = 0;
             }
+            if cur == '"' || cur == '\'' {
+                let prefix = collected.iter().collect::<String>().to_lowercase();
+                let (raw, format) = if matches!(prefix.as_str(), "" | "r" | "f" | "u" | "rf" | "fr") {
+                    collected.clear();
+                    (prefix.contains('r'), prefix.contains('f'))
+                } else {
+                    push_collected(token_ref, collected, line_number)?;
+                    (false, false)
+                };
+                if let Some(n) = next {
+                    chars.insert(0, n);
+                }
+                let start = line_number;
+                let text = lex_string(cur, &mut chars, raw, &mut line_number)?;
+                let token = if format { Token::FormatString(split_format(text, start)?) } else { Token::StringLiteral(text) };
+                token_ref.push(TokenEntry { token, line_number: start });
+                next = if chars.is_empty() { None } else { Some(chars.remove(0)) };
+                continue;
+            }
             match cur {
                 ':' => push_with_extra(Token::Colon, token_ref, collected, line_number)?,
                 '[' => push_with_extra(match next {
@@ -84,6 +83,12 @@ impl ToTokens for String {
                 '(' => push_with_extra(Token::OpenParenthesis, token_ref, collected, line_number)?,
                 ')' => push_with_extra(Token::CloseParenthesis, token_ref, collected, line_number)?,
                 ',' => push_with_extra(Token::Comma, token_ref, collected, line_number)?,
+                '.' if collected.first().is_some_and(|c| c.is_alphabetic() || *c == '_')
+                    || (collected.is_empty() && !next.is_some_and(|c| c.is_ascii_digit())) => {
+                    push_with_extra(Token::Dot, token_ref, collected, line_number)?
+                }
+                // the sign of an exponent belongs to the number, as in 1e-5
+                '+' | '-' if is_exponent(collected) => collected.push(cur),
                 '+' => push_with_extra(match next {
                     Some('=') => {
                         next = Some(chars.remove(0));
@@ -115,6 +120,15 @@ impl ToTokens for String {
                         next = Some(chars.remove(0));
                         Token::MultiplyEquals
                     }
+                    Some('*') => {
+                        next = Some(chars.remove(0));
+                        if next == Some('=') {
+                            next = Some(chars.remove(0));
+                            Token::PowerEquals
+                        } else {
+                            Token::DoubleStar
+                        }
+                    }
                     _ => { Token::Star }
                 }, token_ref, collected, line_number)?,
                 '/' => push_with_extra(match next {
@@ -122,6 +136,15 @@ impl ToTokens for String {
                         next = Some(chars.remove(0));
                         Token::DivideEquals
                     }
+                    Some('/') => {
+                        next = Some(chars.remove(0));
+                        if next == Some('=') {
+                            next = Some(chars.remove(0));
+                            Token::FloorDivideEquals
+                        } else {
+                            Token::DoubleSlash
+                        }
+                    }
                     _ => Token::Slash,
                 }, token_ref, collected, line_number)?,
                 '%' => push_with_extra(match next {
@@ -181,11 +204,16 @@ impl ToTokens for String {
                     }
                     _ => Token::Or,
                 }, token_ref, collected, line_number)?,
+                '^' => push_with_extra(Token::Caret, token_ref, collected, line_number)?,
+                '@' => push_with_extra(Token::At, token_ref, collected, line_number)?,
+                '~' => push_with_extra(Token::Tilde, token_ref, collected, line_number)?,
                 '\n' => {
+                    // blank lines never reach the parser
+                    if !counting_spaces {
+                        push_with_extra(Token::NewLine, token_ref, collected, line_number)?;
+                    }
                     line_number = line_number + 1;
-                    push_collected(token_ref, collected, line_number)?;
                     counting_spaces = true;
-                    push_with_extra(Token::NewLine, token_ref, collected, line_number)?;
                 }
                 ' ' | '\r' | '\t' => push_collected(token_ref, collected, line_number)?,
                 _ => { collected.push(cur) }
@@ -194,6 +222,11 @@ impl ToTokens for String {
         if !collected.is_empty() {
             push_collected(&mut tokens, collected, line_number)?;
         }
+        if let Some(text) = comment {
+            push_comment(text, counting_spaces, space_count, &mut tokens, line_number);
+        }
+        drop_imports(&mut tokens)?;
+        indent_comments(&mut tokens);
         loop {
             if tokens.len() < 2 { break; }
             if matches!(&tokens[0].token, Token::Spaces(_)) && matches!(&tokens[1].token, Token::Spaces(_)) {
@@ -204,6 +237,169 @@ impl ToTokens for String {
     }
 }
 
+/// Reads the rest of a string literal opened by `quote`, handling triple quotes and escape sequences.
+fn lex_string(quote: char, chars: &mut Vec<char>, raw: bool, line_number: &mut usize) -> Result<String, String> {
+    let start = *line_number;
+    let triple = chars.len() >= 2 && chars[0] == quote && chars[1] == quote;
+    if triple {
+        chars.drain(..2);
+    }
+    let mut text = String::new();
+    loop {
+        if chars.is_empty() {
+            return Err(format!("unterminated string starting on line {}", start));
+        }
+        let cur = chars.remove(0);
+        if cur == quote {
+            if !triple {
+                break;
+            }
+            if chars.len() >= 2 && chars[0] == quote && chars[1] == quote {
+                chars.drain(..2);
+                break;
+            }
+        }
+        if cur == '\n' {
+            if !triple {
+                return Err(format!("unterminated string starting on line {}", start));
+            }
+            *line_number += 1;
+        }
+        if cur != '\\' || raw || chars.is_empty() {
+            text.push(cur);
+            continue;
+        }
+        let escaped = chars.remove(0);
+        match escaped {
+            'n' => text.push('\n'),
+            't' => text.push('\t'),
+            '0' => text.push('\0'),
+            '\\' | '\'' | '"' => text.push(escaped),
+            '\n' => *line_number += 1,
+            'x' => {
+                let digits: String = chars.drain(..2.min(chars.len())).collect();
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(c) => text.push(c),
+                    None => return Err(format!("invalid escape '\\x{}' on line {}", digits, line_number))
+                }
+            }
+            c => {
+                text.push('\\');
+                text.push(c);
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Splits the body of an f-string into literal text and the tokens of each `{expression}`.
+fn split_format(text: String, line_number: usize) -> Result<Vec<FormatPart>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(cur) = chars.next() {
+        match cur {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err(format!("single '}}' is not allowed in f-string on line {}", line_number)),
+            '{' => {
+                let mut expression = String::new();
+                let mut depth = 0;
+                loop {
+                    match chars.next() {
+                        None => return Err(format!("expected '}}' in f-string on line {}", line_number)),
+                        Some('}') if depth == 0 => break,
+                        Some('!') | Some(':') if depth == 0 && chars.peek() != Some(&'=') => {
+                            return Err(format!("format specifiers and conversions in f-strings are not supported on line {}", line_number));
+                        }
+                        Some(c) => {
+                            match c {
+                                '(' | '[' | '{' => depth += 1,
+                                ')' | ']' | '}' => depth -= 1,
+                                _ => {}
+                            }
+                            expression.push(c);
+                        }
+                    }
+                }
+                let tokens: Vec<TokenEntry> = expression.to_tokens()?.into_iter()
+                    .filter(|entry| !matches!(entry.token, Token::Spaces(_) | Token::NewLine))
+                    .map(|entry| TokenEntry { token: entry.token, line_number })
+                    .collect();
+                if tokens.is_empty() {
+                    return Err(format!("empty expression in f-string on line {}", line_number));
+                }
+                if !literal.is_empty() {
+                    parts.push(FormatPart::Text(std::mem::take(&mut literal)));
+                }
+                parts.push(FormatPart::Expression(tokens));
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(FormatPart::Text(literal));
+    }
+    Ok(parts)
+}
+
+/// Keeps comments that sit on their own line, since they may be emitted or carry a directive.
+fn push_comment(text: String, own_line: bool, indent: usize, tokens: &mut Vec<TokenEntry>, line_number: usize) {
+    let text = text.trim().to_string();
+    if own_line {
+        tokens.push(TokenEntry { token: Token::Spaces(indent), line_number });
+        tokens.push(TokenEntry { token: Token::Comment(text), line_number });
+        tokens.push(TokenEntry { token: Token::NewLine, line_number });
+    } else if text.starts_with("crunch:") {
+        crate::warning(&format!("directive on line {} is ignored, it must be on its own line before a statement or function", line_number));
+    }
+}
+
+/// typing only annotates and @dataclass is built in, so their top-level imports are dropped like blank lines. This
+/// runs on tokens, so the same text inside a string is kept.
+fn drop_imports(tokens: &mut Vec<TokenEntry>) -> Result<(), String> {
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i..] {
+            [TokenEntry { token: Token::Spaces(0), .. }, TokenEntry { token: Token::Identifier(from), .. },
+             TokenEntry { token: Token::Identifier(module), .. }, TokenEntry { token: Token::Import, .. }, ..]
+                if from == "from" && matches!(module.as_str(), "typing" | "dataclasses") => {
+                let end = tokens[i..].iter().position(|entry| entry.token == Token::NewLine).map_or(tokens.len(), |end| i + end + 1);
+                tokens.drain(i..end);
+            }
+            [TokenEntry { token: Token::Import, line_number }, ..] => {
+                return Err(format!("import statements are not supported on line {}", line_number));
+            }
+            _ => i += 1,
+        }
+    }
+    Ok(())
+}
+
+/// Python lets comments be indented freely, so each comment line takes the indentation of the code after it.
+/// Comments with no code after them, or directly before an `else`, have nothing to belong to and are dropped.
+fn indent_comments(tokens: &mut Vec<TokenEntry>) {
+    let mut indent = None;
+    let mut i = tokens.len();
+    while i > 0 {
+        i -= 1;
+        if let Token::Spaces(count) = tokens[i].token {
+            match (tokens.get(i + 1).map(|entry| &entry.token), indent) {
+                (Some(Token::Comment(_)), Some(indent)) => tokens[i].token = Token::Spaces(indent),
+                (Some(Token::Comment(_)), None) => { tokens.drain(i..i + 3); }
+                (Some(Token::Else | Token::Elif), _) => indent = None,
+                _ => indent = Some(count),
+            }
+        }
+    }
+}
+
 fn push_with_extra(token: Token, tokens: &mut Vec<TokenEntry>, collected: &mut Vec<char>, line_number: usize) -> Result<(), String> {
     push_collected(tokens, collected, line_number)?;
     tokens.push(TokenEntry { token, line_number });
@@ -219,18 +415,35 @@ fn push_collected(tokens: &mut Vec<TokenEntry>, collected: &mut Vec<char>, line_
             "if" => Token::If,
             "while" => Token::While,
             "else" => Token::Else,
+            "elif" => Token::Elif,
             "for" => Token::For,
             "int" => Token::IntType,
+            "bool" => Token::BoolType,
             "str" => Token::StringType,
             "def" => Token::Def,
+            "class" => Token::Class,
             "pass" => Token::Pass,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
             "list" => Token::List,
+            "tuple" => Token::Tuple,
             "Matrix" => Token::Matrix,
             "Complex" => Token::Complex,
             "del" => Token::Del,
+            "assert" => Token::Assert,
+            "global" => Token::Global,
+            "try" => Token::Try,
+            "except" => Token::Except,
+            "finally" => Token::Finally,
+            "as" => Token::As,
+            "nonlocal" => Token::Nonlocal,
+            "lambda" => Token::Lambda,
             "float" => Token::Float,
             "in" => Token::In,
-            "import" => return Err("import statements are not supported.".to_string()),
+            "and" => Token::AndAnd,
+            "or" => Token::OrOr,
+            "not" => Token::Not,
+            "import" => Token::Import,
             v => eval_literal(v.to_string())?
         };
         tokens.push( TokenEntry { token, line_number }
@@ -239,20 +452,81 @@ fn push_collected(tokens: &mut Vec<TokenEntry>, collected: &mut Vec<char>, line_
     Ok(())
 }
 
+fn is_exponent(collected: &[char]) -> bool {
+    collected.first().is_some_and(|c| c.is_ascii_digit() || *c == '.')
+        && collected.last().is_some_and(|c| matches!(c, 'e' | 'E'))
+        && !matches!(collected.get(1), Some('x' | 'X'))
+}
+
 fn eval_literal(finalized: String) -> Result<Token, String> {
-    if let Ok(b) = finalized.parse::<bool>() {
-        return Ok(Token::BoolLiteral(b));
-    }
-    if let Ok(i) = finalized.parse::<i64>() {
-        return Ok(Token::IntLiteral(i));
+    match finalized.as_str() {
+        "True" => return Ok(Token::BoolLiteral(true)),
+        "False" => return Ok(Token::BoolLiteral(false)),
+        _ => {}
     }
-    if let Ok(f) = finalized.parse::<f64>() {
-        return Ok(Token::FloatLiteral(f));
+    if finalized.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return eval_number(&finalized);
     }
     Ok(Token::Identifier(finalized))
 }
 
-#[derive(Clone)]
+/// Lexes every Python numeric literal form, warning when the value cannot be held exactly by the calculator.
+fn eval_number(literal: &str) -> Result<Token, String> {
+    let invalid = || format!("invalid numeric literal '{}'", literal);
+    if literal.ends_with('_') || literal.contains("__") || literal.contains("_.") || literal.contains("._") {
+        return Err(invalid());
+    }
+    let text = literal.replace('_', "");
+    let radix = match text.get(..2).map(|prefix| prefix.to_ascii_lowercase()).as_deref() {
+        Some("0x") => Some(16),
+        Some("0o") => Some(8),
+        Some("0b") => Some(2),
+        _ => None
+    };
+    if let Some(radix) = radix {
+        let value = u64::from_str_radix(&text[2..], radix).map_err(|_| invalid())?;
+        return Ok(integer_literal(value as f64, literal));
+    }
+    if let Some(imaginary) = text.strip_suffix(['j', 'J']) {
+        let value = imaginary.parse::<f64>().map_err(|_| invalid())?;
+        check_precision(imaginary, value, literal);
+        return Ok(Token::ImaginaryLiteral(value));
+    }
+    if text.contains(['.', 'e', 'E']) {
+        let value = text.parse::<f64>().map_err(|_| invalid())?;
+        check_precision(&text, value, literal);
+        return Ok(Token::FloatLiteral(value));
+    }
+    if text.len() > 1 && text.starts_with('0') && text.chars().any(|c| c != '0') {
+        return Err(format!("leading zeros in decimal literal '{}' are not allowed, use an 0o prefix for octal", literal));
+    }
+    let value = text.parse::<f64>().map_err(|_| invalid())?;
+    Ok(integer_literal(value, literal))
+}
+
+fn integer_literal(value: f64, literal: &str) -> Token {
+    check_precision(&format!("{}", value), value, literal);
+    if value <= i64::MAX as f64 {
+        Token::IntLiteral(value as i64)
+    } else {
+        Token::FloatLiteral(value)
+    }
+}
+
+/// Real numbers on the TI-84 carry 14 significant digits and exponents between -99 and 99.
+fn check_precision(digits: &str, value: f64, literal: &str) {
+    if value != 0.0 && !(1e-99..1e100).contains(&value) {
+        crate::warning(&format!("'{}' is outside the calculator's range of 1E-99 to 9.9999999999999E99", literal));
+        return;
+    }
+    let mantissa = digits.split(['e', 'E']).next().unwrap_or(digits);
+    let significant = mantissa.chars().filter(char::is_ascii_digit).collect::<String>();
+    if significant.trim_start_matches('0').trim_end_matches('0').len() > 14 {
+        crate::warning(&format!("'{}' has more than the calculator's 14 significant digits and will be rounded", literal));
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) struct TokenEntry {
     pub(crate) token: Token,
     pub(crate) line_number: usize,
@@ -275,12 +549,15 @@ pub enum Token {
     CloseParenthesis,
     EmptyList,
     Comma,
+    Dot,
     Plus,
     Minus,
     Increment,
     Decrement,
     Star,
+    DoubleStar,
     Slash,
+    DoubleSlash,
     Percent,
     Equals,
     EqualsEquals,
@@ -293,7 +570,9 @@ pub enum Token {
     MinusEquals,
     MultiplyEquals,
     DivideEquals,
+    FloorDivideEquals,
     PercentEquals,
+    PowerEquals,
     LeftShift,
     RightShift,
     Not,
@@ -301,27 +580,53 @@ pub enum Token {
     And,
     OrOr,
     Or,
+    Caret,
+    Tilde,
+    At,
     StringLiteral(String),
+    FormatString(Vec<FormatPart>),
     BoolLiteral(bool),
     IntLiteral(i64),
     FloatLiteral(f64),
+    ImaginaryLiteral(f64),
     Return,
     If,
     While,
     Else,
+    Elif,
     For,
     IntType,
+    BoolType,
     StringType,
     Def,
     Pass,
+    Break,
+    Continue,
     Spaces(usize),
     List,
+    Tuple,
     Del,
+    Assert,
+    Global,
+    Try,
+    Except,
+    Finally,
+    As,
+    Import,
+    Nonlocal,
+    Lambda,
     Float,
     EOF,
     Matrix,
     Complex,
+    Class,
     RightArrow,
     NewLine,
+    Comment(String),
     In,
-}
\ No newline at end of file
+}
+#[derive(Debug, PartialEq, Clone)]
+pub enum FormatPart {
+    Text(String),
+    Expression(Vec<TokenEntry>),
+}
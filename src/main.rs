@@ -7,12 +7,14 @@ use colored::Colorize;
 use std::path::{Path as IoPath, PathBuf};
 use std::io::{Read, Result as IoResult};
 use crate::lexer::{ToTokens, TokenEntry};
-use crate::types::Function;
+use crate::types::{Constant, Function, Record};
 
 mod lexer;
 mod types;
 mod parser;
 mod statements;
+mod codegen;
+mod constants;
 
 fn main() {
     let mut args: Vec<String> = env::args().collect();
@@ -22,17 +24,22 @@ fn main() {
     args.push(String::from("scripts/test.py"));
     /* ----------------- */
 
+    let options = codegen::Options {
+        comments: args.iter().any(|arg| arg == "--comments"),
+        debug: args.iter().any(|arg| arg == "--debug"),
+    };
+    args.retain(|arg| !arg.starts_with("--"));
     if args.len() != 1 {
-        error("Usage: <source dir/file path>");
+        error("Usage: [--comments] [--debug] <source dir/file path>");
     }
 
-    match compile(args) {
+    match compile(args, &options) {
         Ok(_) => {}
         Err(e) => error(&e),
     }
 }
 
-fn compile(mut args: Vec<String>) -> Result<(), String> {
+fn compile(mut args: Vec<String>, options: &codegen::Options) -> Result<(), String> {
     let input_path_string = args.remove(0);
     let input_path = IoPath::new(&input_path_string);
     if !input_path.exists() {
@@ -60,6 +67,17 @@ fn compile(mut args: Vec<String>) -> Result<(), String> {
     }
     println!("{:?}", names);
 
+    for program in transpile(&names, contents, options)? {
+        println!("PROGRAM:{}", program.name);
+        for line in program.lines {
+            println!(":{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Compiles the Python files `names`, whose sources are `contents`, into TI-Basic programs.
+fn transpile(names: &[&str], contents: Vec<String>, options: &codegen::Options) -> Result<Vec<codegen::Program>, String> {
     let mut tokens : Vec<Vec<TokenEntry>> = Vec::new();
     for (i, content) in contents.into_iter().enumerate() {
         unwrap_io(std::io::stdout().flush());
@@ -70,14 +88,20 @@ fn compile(mut args: Vec<String>) -> Result<(), String> {
         tokens.push(token_vec);
     }
 
-    let mut programs : Vec<Vec<Function>> = Vec::new();
-    for vec in tokens {
-        programs.push(parser::parse_tokens(vec)?);
-    }
-    for program in programs {
-        println!("{:#?}", program);
+    let mut functions : Vec<Function> = Vec::new();
+    let mut records : Vec<Record> = Vec::new();
+    let mut constants : Vec<Constant> = Vec::new();
+    for (i, vec) in tokens.into_iter().enumerate() {
+        let (mut parsed, declared, defined) = parser::parse_tokens(vec)?;
+        for function in &mut parsed {
+            function.file = names[i].to_string();
+        }
+        functions.extend(parsed);
+        records.extend(declared);
+        constants.extend(defined);
     }
-    Ok(())
+    constants::fold(&mut functions, &mut records, constants)?;
+    codegen::generate(&functions, &records, options)
 }
 
 fn error(message: &str) -> ! {
@@ -85,6 +109,10 @@ fn error(message: &str) -> ! {
     exit(1);
 }
 
+fn warning(message: &str) {
+    eprintln!("{}: {}", "Crunch Warning".yellow(), message);
+}
+
 fn walk_dir(path: &IoPath, files : &mut Vec<PathBuf>) -> IoResult<()> {
     if path.is_file() {
         match path.extension() {
@@ -1,15 +1,57 @@
 use crate::lexer::{Token, TokenEntry};
-use crate::statements::{parse_statement};
-use crate::types::{Function, Parameter, Type};
+use crate::statements::{parse_expression, parse_statement};
+use crate::types::{Constant, Directive, Function, Parameter, Record, Type};
 
-pub(crate) fn parse_tokens(mut tokens: Vec<TokenEntry>) -> Result<Vec<Function> ,String> {
+/// Everything a file declares at its top level.
+pub(crate) type Module = (Vec<Function>, Vec<Record>, Vec<Constant>);
+
+pub(crate) fn parse_tokens(mut tokens: Vec<TokenEntry>) -> Result<Module ,String> {
     let mut functions = Vec::new();
+    let mut records = Vec::new();
+    let mut constants = Vec::new();
+    let mut directives = Vec::new();
     while !tokens.is_empty() {
         let token = eat(&mut tokens);
         if let Token::Spaces(_) = token.token {
             let next = eat(&mut tokens);
             match next.token {
-                Token::Def => functions.push(parse_function(&mut tokens)?),
+                Token::Def => {
+                    let mut function = parse_function(&mut tokens)?;
+                    function.directives = std::mem::take(&mut directives);
+                    functions.push(function);
+                }
+                // dataclasses only bundle typed fields, so the decorator is optional
+                Token::At => {
+                    let decorator = eat(&mut tokens);
+                    match decorator.token {
+                        Token::Identifier(name) if name == "dataclass" => {}
+                        t => { return Err(format!("Expected 'dataclass' after '@', but found {:?} on line {}", t, decorator.line_number)) }
+                    }
+                    let expect = eat(&mut tokens);
+                    match expect.token {
+                        Token::NewLine => {}
+                        t => { return Err(format!("Expected NewLine after decorator, but found {:?} on line {}", t, expect.line_number)) }
+                    }
+                    if !matches!(tokens.get(1).map(|token| &token.token), Some(Token::Class)) {
+                        return Err(format!("Expected 'class' after '@dataclass' on line {}", next.line_number));
+                    }
+                }
+                Token::Class => {
+                    if !directives.is_empty() {
+                        return Err(format!("directives cannot apply to a class on line {}", next.line_number));
+                    }
+                    records.push(parse_record(&mut tokens)?);
+                }
+                Token::Identifier(name) => {
+                    if !directives.is_empty() {
+                        return Err(format!("directives cannot apply to a constant on line {}", next.line_number));
+                    }
+                    constants.push(parse_constant(name, next.line_number, &mut tokens)?);
+                }
+                Token::Comment(text) => {
+                    eat(&mut tokens);
+                    directives.extend(parse_directives(&text, next.line_number)?.unwrap_or_default());
+                }
                 Token::NewLine => {}
                 _ => {
                     return Err(format!("Expected 'def', but found {:?} on line {}", token.token, token.line_number));
@@ -29,7 +71,96 @@ pub(crate) fn parse_tokens(mut tokens: Vec<TokenEntry>) -> Result<Vec<Function>
         }
     }
 
-    Ok(functions)
+    Ok((functions, records, constants))
+}
+
+fn parse_record(tokens : &mut Vec<TokenEntry>) -> Result<Record ,String> {
+    let token = eat(tokens);
+    let name = match token.token {
+        Token::Identifier(name) => name,
+        t => { return Err(format!("Expected class name, but found {:?} on line {}", t, token.line_number)) }
+    };
+    let expect = eat(tokens);
+    match expect.token {
+        Token::Colon => {}
+        t => { return Err(format!("Expected ':' after class name, but found {:?} on line {}", t, expect.line_number)) }
+    }
+    let expect = eat(tokens);
+    match expect.token {
+        Token::NewLine => {}
+        t => { return Err(format!("Expected NewLine after class declaration, but found {:?} on line {}", t, expect.line_number)) }
+    }
+    let spacing_req = match peek(tokens) {
+        Token::Spaces(v) if *v > 0 => *v,
+        t => { return Err(format!("Expected an indented field after class '{}', but found {:?} on line {}", name, t, expect.line_number)) }
+    };
+    let mut fields: Vec<Parameter> = Vec::new();
+    while matches!(peek(tokens), Token::Spaces(count) if *count == spacing_req) {
+        let _ = eat(tokens);
+        if let Token::Comment(_) = peek(tokens) {
+            let _ = eat(tokens);
+        } else {
+            let field = parse_parameter(tokens)?;
+            if fields.iter().any(|other| other.name == field.name) {
+                return Err(format!("class '{}' declares field '{}' twice", name, field.name));
+            }
+            push_parameter(&mut fields, field, expect.line_number)?;
+        }
+        let expect = eat(tokens);
+        match expect.token {
+            Token::NewLine | Token::EOF => {}
+            t => { return Err(format!("Expected NewLine after class field, but found {:?} on line {}", t, expect.line_number)) }
+        }
+    }
+    if let Token::Spaces(count) = peek(tokens) {
+        if *count > spacing_req {
+            return Err(format!("found extra indentation on line {}", eat(tokens).line_number));
+        }
+    }
+    if fields.is_empty() {
+        return Err(format!("class '{}' must declare at least one field", name));
+    }
+    Ok(Record { name, fields })
+}
+
+/// Reads `NAME = value`, `name: Final = value` or `name: Final[type] = value` at the top of a file.
+fn parse_constant(name: String, line_number: usize, tokens : &mut Vec<TokenEntry>) -> Result<Constant, String> {
+    let mut is_final = false;
+    let mut typetype = None;
+    if matches!(peek(tokens), Token::Colon) {
+        let _ = eat(tokens);
+        if matches!(peek(tokens), Token::Identifier(annotation) if annotation == "Final") {
+            let _ = eat(tokens);
+            is_final = true;
+            if matches!(peek(tokens), Token::OpenBracket) {
+                let _ = eat(tokens);
+                typetype = Some(parse_type(tokens)?);
+                let expect = eat(tokens);
+                match expect.token {
+                    Token::CloseBracket => {}
+                    t => { return Err(format!("Expected ']' after Final type, but found {:?} on line {}", t, expect.line_number)) }
+                }
+            }
+        } else {
+            typetype = Some(parse_type(tokens)?);
+        }
+    }
+    let all_caps = name.contains(|c: char| c.is_ascii_uppercase()) && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if !is_final && !all_caps {
+        return Err(format!("only constants, named in ALL_CAPS or annotated Final, can be assigned outside of a function, but found '{}' on line {}", name, line_number));
+    }
+    let expect = eat(tokens);
+    match expect.token {
+        Token::Equals => {}
+        t => { return Err(format!("Expected '=' after constant '{}', but found {:?} on line {}", name, t, expect.line_number)) }
+    }
+    let value = parse_expression(tokens, 0)?;
+    let expect = eat(tokens);
+    match expect.token {
+        Token::NewLine | Token::EOF => {}
+        t => { return Err(format!("Expected NewLine after constant '{}', but found {:?} on line {}", name, t, expect.line_number)) }
+    }
+    Ok(Constant { name, typetype, value })
 }
 
 fn parse_function(tokens : &mut Vec<TokenEntry>) -> Result<Function ,String> {
@@ -45,7 +176,7 @@ fn parse_function(tokens : &mut Vec<TokenEntry>) -> Result<Function ,String> {
     }
     let mut parameters = Vec::new();
     if !matches!(peek(tokens), Token::CloseParenthesis) {
-        parameters.push(parse_var_dec(tokens)?);
+        push_parameter(&mut parameters, parse_parameter(tokens)?, expect.line_number)?;
         loop {
             if matches!(peek(tokens), Token::CloseParenthesis) { break; }
             let expect = eat(tokens);
@@ -53,17 +184,17 @@ fn parse_function(tokens : &mut Vec<TokenEntry>) -> Result<Function ,String> {
                 Token::Comma => {}
                 t => { return Err(format!("Expected ',' after function parameter, but found {:?} on line {}", t, expect.line_number)) }
             }
-            parameters.push(parse_var_dec(tokens)?);
+            push_parameter(&mut parameters, parse_parameter(tokens)?, expect.line_number)?;
         }
     }
-    let parameters = parameters.into_iter().map(|(name, typetype)| Parameter { name, typetype }).collect();
     let _ = eat(tokens);
     let return_type = if matches!(peek(tokens), Token::RightArrow) {
         let _ = eat(tokens);
         let typetype = parse_type(tokens)?;
-        match typetype {
-            Type::Int | Type::Float | Type::Complex => {}
-            t => { return Err(format!("Expected return type of 'int', 'float' or 'Complex', but found {:?} on line {}", t, expect.line_number)) }
+        match &typetype {
+            Type::Bool | Type::Int | Type::Float | Type::Complex => {}
+            Type::Tuple(types) if types.iter().all(|t| !matches!(t, Type::Tuple(_) | Type::RecordList(_))) => {}
+            t => { return Err(format!("Expected return type of 'bool', 'int', 'float', 'Complex' or a tuple, but found {:?} on line {}", t, expect.line_number)) }
         }
         Some(typetype)
     } else {
@@ -102,7 +233,45 @@ fn parse_function(tokens : &mut Vec<TokenEntry>) -> Result<Function ,String> {
         }
     }
 
-    Ok(Function { name, parameters, return_type, statements })
+    Ok(Function { name, parameters, return_type, statements, directives: Vec::new(), file: String::new() })
+}
+
+/// Reads the directives of a `# crunch: ...` comment, or `None` if the comment is an ordinary one.
+pub(crate) fn parse_directives(comment: &str, line_number: usize) -> Result<Option<Vec<Directive>>, String> {
+    let Some(rest) = comment.strip_prefix("crunch:") else { return Ok(None) };
+    let mut directives = Vec::new();
+    for word in rest.split([',', ' ']).filter(|word| !word.is_empty()) {
+        directives.push(match word.split_once('=') {
+            None if word == "inline" => Directive::Inline,
+            None if word == "noopt" => Directive::NoOpt,
+            Some(("var", variable)) if !variable.is_empty() => Directive::Var(variable.to_string()),
+            _ => return Err(format!("Unknown directive '{}' on line {}", word, line_number))
+        });
+    }
+    if directives.is_empty() {
+        return Err(format!("Expected a directive after 'crunch:' on line {}", line_number));
+    }
+    Ok(Some(directives))
+}
+
+/// Reads `name: type`, optionally followed by `= default`.
+fn parse_parameter(tokens : &mut Vec<TokenEntry>) -> Result<Parameter, String> {
+    let (name, typetype) = parse_var_dec(tokens)?;
+    let default = if matches!(peek(tokens), Token::Equals) {
+        let _ = eat(tokens);
+        Some(parse_expression(tokens, 0)?)
+    } else {
+        None
+    };
+    Ok(Parameter { name, typetype, default })
+}
+
+fn push_parameter(parameters: &mut Vec<Parameter>, parameter: Parameter, line_number: usize) -> Result<(), String> {
+    if parameter.default.is_none() && parameters.last().is_some_and(|last| last.default.is_some()) {
+        return Err(format!("'{}' without a default follows a parameter with one on line {}", parameter.name, line_number));
+    }
+    parameters.push(parameter);
+    Ok(())
 }
 
 fn parse_var_dec(tokens : &mut Vec<TokenEntry>) -> Result<(String, Type), String> {
@@ -123,10 +292,75 @@ pub(crate) fn parse_type(tokens : &mut Vec<TokenEntry>) -> Result<Type ,String>
     let token = eat(tokens);
     Ok(match token.token {
         Token::IntType => Type::Int,
+        Token::BoolType => Type::Bool,
         Token::StringType => Type::String,
         Token::Float => Type::Float,
         Token::Matrix => Type::Matrix,
         Token::Complex => Type::Complex,
+        Token::Identifier(name) if name == "Final" => {
+            return Err(format!("Final constants can only be declared outside of a function on line {}", token.line_number));
+        }
+        Token::Identifier(name) if name == "Callable" => {
+            let expect = eat(tokens);
+            match expect.token {
+                Token::OpenBracket => {}
+                t => { return Err(format!("Expected '[' after Callable type, but found {:?} on line {}", t, expect.line_number)) }
+            }
+            let expect = eat(tokens);
+            match expect.token {
+                Token::OpenBracket => {}
+                t => { return Err(format!("Expected '[' before Callable parameter types, but found {:?} on line {}", t, expect.line_number)) }
+            }
+            let mut parameters = Vec::new();
+            if matches!(peek(tokens), Token::CloseBracket) {
+                let _ = eat(tokens);
+            } else {
+                parameters.push(parse_type(tokens)?);
+                loop {
+                    let expect = eat(tokens);
+                    match expect.token {
+                        Token::Comma => parameters.push(parse_type(tokens)?),
+                        Token::CloseBracket => break,
+                        t => { return Err(format!("Expected ',' or ']' in Callable parameter types, but found {:?} on line {}", t, expect.line_number)) }
+                    }
+                }
+            }
+            let expect = eat(tokens);
+            match expect.token {
+                Token::Comma => {}
+                t => { return Err(format!("Expected ',' before Callable return type, but found {:?} on line {}", t, expect.line_number)) }
+            }
+            let returned = if matches!(peek(tokens), Token::Identifier(name) if name == "None") {
+                let _ = eat(tokens);
+                None
+            } else {
+                Some(Box::new(parse_type(tokens)?))
+            };
+            let expect = eat(tokens);
+            match expect.token {
+                Token::CloseBracket => {}
+                t => { return Err(format!("Expected ']' after Callable type, but found {:?} on line {}", t, expect.line_number)) }
+            }
+            Type::Callable(parameters, returned)
+        }
+        Token::Identifier(name) => Type::Record(name),
+        Token::Tuple => {
+            let expect = eat(tokens);
+            match expect.token {
+                Token::OpenBracket => {}
+                t => { return Err(format!("Expected '[' after tuple type, but found {:?} on line {}", t, expect.line_number)) }
+            }
+            let mut types = vec![parse_type(tokens)?];
+            loop {
+                let expect = eat(tokens);
+                match expect.token {
+                    Token::Comma => types.push(parse_type(tokens)?),
+                    Token::CloseBracket => break,
+                    t => { return Err(format!("Expected ',' or ']' in tuple type, but found {:?} on line {}", t, expect.line_number)) }
+                }
+            }
+            Type::Tuple(types)
+        }
         Token::List => {
             let expect = eat(tokens);
             match expect.token {
@@ -138,7 +372,8 @@ pub(crate) fn parse_type(tokens : &mut Vec<TokenEntry>) -> Result<Type ,String>
                 Token::IntType => Type::IntList,
                 Token::Complex => Type::ComplexList,
                 Token::Float => Type::FloatList,
-                t => { return Err(format!("Expected 'int', 'Complex', or 'float' for list generic, but found {:?} on line {}", t, expect.line_number)) }
+                Token::Identifier(name) => Type::RecordList(name),
+                t => { return Err(format!("Expected 'int', 'Complex', 'float' or a class for list generic, but found {:?} on line {}", t, expect.line_number)) }
             };
             let expect = eat(tokens);
             match expect.token {
@@ -1,8 +1,8 @@
-use crate::lexer::{Token, TokenEntry};
+use crate::lexer::{FormatPart, Token, TokenEntry};
 use std::fmt::{Display, Formatter};
 use crate::parser;
 use crate::parser::{eat, peek};
-use crate::types::Type;
+use crate::types::{Directive, Type};
 
 // Thank you, Core Dumped, for the amazing video on Pratt Parsing!
 pub(crate) fn parse_statement(tokens : &mut Vec<TokenEntry>, spacing_req : Option<usize>) -> Result<Option<Statement>, String> {
@@ -30,13 +30,44 @@ pub(crate) fn parse_statement(tokens : &mut Vec<TokenEntry>, spacing_req : Optio
         Token::NewLine => {
             return Ok(None)
         }
+        Token::Comment(text) => {
+            eat(tokens);
+            match parser::parse_directives(&text, token.line_number)? {
+                // directives belong to the statement that follows them
+                Some(directives) => {
+                    let mut statement = match parse_statement(tokens, spacing_req)? {
+                        Some(statement) => statement,
+                        None => return Err(format!("Expected a statement after the directive on line {}", token.line_number))
+                    };
+                    statement.directives.splice(0..0, directives);
+                    return Ok(Some(statement))
+                }
+                None => Statement::new(StatementType::Comment(text), token.line_number)
+            }
+        }
         Token::If => {
             let condition = parse_expression(tokens, 0)?;
             let body = parse_statement_body(tokens)?;
-            let else_body = if matches!(peek(tokens), Token::Else) {
+            let continues = match (tokens.first(), tokens.get(1)) {
+                (Some(TokenEntry { token: Token::Spaces(count), .. }), Some(TokenEntry { token: Token::Else | Token::Elif, .. })) => {
+                    spacing_req.is_none_or(|req| req == *count)
+                }
+                _ => false
+            };
+            if continues {
+                eat(tokens);
+            }
+            let else_body = if matches!(peek(tokens), Token::Else | Token::Elif) {
                 let else_token = eat(tokens);
-                if matches!(peek(tokens), Token::If) {
-                    parse_statement(tokens, None)?
+                if matches!(else_token.token, Token::Elif) || matches!(peek(tokens), Token::If) {
+                    if matches!(else_token.token, Token::Else) {
+                        eat(tokens);
+                    }
+                    tokens.insert(0, TokenEntry { token: Token::If, line_number: else_token.line_number });
+                    if let Some(req) = spacing_req {
+                        tokens.insert(0, TokenEntry { token: Token::Spaces(req), line_number: else_token.line_number });
+                    }
+                    parse_statement(tokens, spacing_req)?
                 } else {
                     Some(Statement::new(StatementType::If(Expression::new(ExpressionType::BoolLiteral(true), else_token.line_number), parse_statement_body(tokens)?, None), else_token.line_number))
                 }
@@ -74,13 +105,87 @@ pub(crate) fn parse_statement(tokens : &mut Vec<TokenEntry>, spacing_req : Optio
                 eat(tokens);
                 None
             } else {
-                Some(parse_expression(tokens, 0)?)
+                let value = parse_expression(tokens, 0)?;
+                Some(new_line(parse_tuple(tokens, value)?, tokens)?)
             };
             Statement::new(StatementType::Return(value), token.line_number)
         }
+        Token::Assert => {
+            let condition = parse_expression(tokens, 0)?;
+            let message = if matches!(peek(tokens), Token::Comma) {
+                eat(tokens);
+                Some(parse_expression(tokens, 0)?)
+            } else {
+                None
+            };
+            new_line(Statement::new(StatementType::Assert(condition, message), token.line_number), tokens)?
+        }
+        Token::Global | Token::Nonlocal => {
+            let mut names = Vec::new();
+            loop {
+                let name = eat(tokens);
+                match name.token {
+                    Token::Identifier(name) => names.push(name),
+                    t => return Err(format!("Expected a variable name, but found {:?} on line {}", t, name.line_number))
+                }
+                if !matches!(peek(tokens), Token::Comma) {
+                    break;
+                }
+                eat(tokens);
+            }
+            // functions cannot be nested, so there is never an enclosing function's variable to refer to
+            if matches!(token.token, Token::Nonlocal) {
+                return Err(format!("no binding for nonlocal '{}' found on line {}", names[0], token.line_number));
+            }
+            new_line(Statement::new(StatementType::Global(names), token.line_number), tokens)?
+        }
+        // `match` is only a keyword at the start of a line ending in ':', so variables may still be called match
+        Token::Identifier(ref name) if name == "match" && ends_with_colon(tokens) => {
+            let subject = parse_expression(tokens, 0)?;
+            Statement::new(StatementType::Match(subject, parse_cases(tokens)?), token.line_number)
+        }
+        Token::Try => {
+            let body = parse_statement_body(tokens)?;
+            let mut handlers = Vec::new();
+            let (mut otherwise, mut finally) = (None, None);
+            loop {
+                let continues = match (tokens.first(), tokens.get(1)) {
+                    (Some(TokenEntry { token: Token::Spaces(count), .. }), Some(TokenEntry { token: Token::Except | Token::Else | Token::Finally, .. })) => {
+                        spacing_req.is_none_or(|req| req == *count)
+                    }
+                    _ => false
+                };
+                if !continues || finally.is_some() {
+                    break;
+                }
+                eat(tokens);
+                let clause = eat(tokens);
+                match clause.token {
+                    Token::Except if otherwise.is_none() => {
+                        handlers.push((parse_exceptions(tokens)?, parse_statement_body(tokens)?));
+                    }
+                    Token::Else if !handlers.is_empty() && otherwise.is_none() => otherwise = Some(parse_statement_body(tokens)?),
+                    Token::Finally => finally = Some(parse_statement_body(tokens)?),
+                    t => return Err(format!("unexpected {:?} after 'try' on line {}", t, clause.line_number))
+                }
+            }
+            if handlers.is_empty() && finally.is_none() {
+                return Err(format!("expected 'except' or 'finally' after 'try' on line {}", token.line_number));
+            }
+            Statement::new(StatementType::Try(body, handlers, otherwise.unwrap_or_default(), finally.unwrap_or_default()), token.line_number)
+        }
+        Token::Pass => new_line(Statement::new(StatementType::Pass, token.line_number), tokens)?,
+        Token::Break => new_line(Statement::new(StatementType::Break, token.line_number), tokens)?,
+        Token::Continue => new_line(Statement::new(StatementType::Continue, token.line_number), tokens)?,
+        Token::Del => {
+            let target = parse_expression(tokens, 0)?;
+            let target = parse_tuple(tokens, target)?;
+            new_line(Statement::new(StatementType::Del(target), token.line_number), tokens)?
+        }
         t  => {
             tokens.insert(0, TokenEntry { token : t, line_number : token.line_number });
             let expression = parse_expression(tokens, 0)?;
+            let expression = parse_tuple(tokens, expression)?;
             let next = eat(tokens);
             new_line(
                 match next.token {
@@ -103,8 +208,11 @@ pub(crate) fn parse_statement(tokens : &mut Vec<TokenEntry>, spacing_req : Optio
                     Token::MinusEquals |
                     Token::MultiplyEquals |
                     Token::DivideEquals |
-                    Token::PercentEquals) => {
+                    Token::FloorDivideEquals |
+                    Token::PercentEquals |
+                    Token::PowerEquals) => {
                         let expr = parse_expression(tokens, 0)?;
+                        let expr = parse_tuple(tokens, expr)?;
                         let expr = {
                             if matches!(t, Token::Equals) {
                                 expr
@@ -114,7 +222,9 @@ pub(crate) fn parse_statement(tokens : &mut Vec<TokenEntry>, spacing_req : Optio
                                     Token::MinusEquals => Operation::Sub(expression.clone(), expr),
                                     Token::MultiplyEquals => Operation::Mul(expression.clone(), expr),
                                     Token::DivideEquals => Operation::Div(expression.clone(), expr),
+                                    Token::FloorDivideEquals => Operation::FloorDiv(expression.clone(), expr),
                                     Token::PercentEquals => Operation::Mod(expression.clone(), expr),
+                                    Token::PowerEquals => Operation::Pow(expression.clone(), expr),
                                     _ => unreachable!()
                                 }), next.line_number)
                             }
@@ -129,6 +239,127 @@ pub(crate) fn parse_statement(tokens : &mut Vec<TokenEntry>, spacing_req : Optio
     val
 }
 
+fn ends_with_colon(tokens: &[TokenEntry]) -> bool {
+    let end = tokens.iter().position(|token| matches!(token.token, Token::NewLine | Token::EOF)).unwrap_or(tokens.len());
+    end > 0 && matches!(tokens[end - 1].token, Token::Colon)
+}
+
+/// Parses the indented `case` blocks of a `match`, each with literal patterns separated by `|`.
+fn parse_cases(tokens: &mut Vec<TokenEntry>) -> Result<Vec<Case>, String> {
+    let expected = eat(tokens);
+    if !matches!(expected.token, Token::Colon) { return Err(format!("expected ':' but found {:?} on line {}", expected.token, expected.line_number)); }
+    let expected = eat(tokens);
+    if !matches!(expected.token, Token::NewLine) { return Err(format!("expected New Line but found {:?} on line {}", expected.token, expected.line_number)); }
+    let spacing_req = match peek(tokens) {
+        Token::Spaces(count) => *count,
+        _ => {
+            let expected = eat(tokens);
+            return Err(format!("expected indentation but found {:?} on line {}", expected.token, expected.line_number));
+        }
+    };
+    let mut cases: Vec<Case> = Vec::new();
+    loop {
+        match peek(tokens) {
+            Token::Spaces(count) if *count < spacing_req => break,
+            Token::Spaces(count) if *count > spacing_req => return Err(format!("found extra indentation on line {}", eat(tokens).line_number)),
+            Token::Spaces(_) => { eat(tokens); }
+            _ => break,
+        }
+        let token = eat(tokens);
+        match token.token {
+            Token::NewLine => continue,
+            Token::Comment(_) => {
+                eat(tokens);
+                continue;
+            }
+            Token::Identifier(name) if name == "case" => {}
+            t => return Err(format!("expected 'case' but found {:?} on line {}", t, token.line_number))
+        }
+        if cases.last().is_some_and(|(patterns, _)| patterns.is_empty()) {
+            return Err(format!("wildcard makes remaining patterns unreachable on line {}", token.line_number));
+        }
+        let mut patterns = Vec::new();
+        let mut wildcard = false;
+        loop {
+            match peek(tokens) {
+                Token::Identifier(name) if name == "_" => {
+                    eat(tokens);
+                    wildcard = true;
+                }
+                _ => {
+                    let pattern = parse_expression(tokens, 6)?;
+                    let literal = match pattern.type_.as_ref() {
+                        ExpressionType::Operation(Operation::Negative(inner)) => matches!(inner.type_.as_ref(), ExpressionType::IntLiteral(_) | ExpressionType::FloatLiteral(_)),
+                        ExpressionType::IntLiteral(_) | ExpressionType::FloatLiteral(_) | ExpressionType::StringLiteral(_) | ExpressionType::BoolLiteral(_) => true,
+                        _ => false,
+                    };
+                    if !literal {
+                        return Err(format!("only literal patterns and '_' are supported on line {}", pattern.line_number));
+                    }
+                    patterns.push(pattern);
+                }
+            }
+            if !matches!(peek(tokens), Token::Or) {
+                break;
+            }
+            eat(tokens);
+        }
+        if wildcard {
+            patterns.clear();
+        }
+        cases.push((patterns, parse_statement_body(tokens)?));
+        if matches!(peek(tokens), Token::EOF) {
+            break;
+        }
+    }
+    if cases.is_empty() {
+        return Err("expected at least one 'case' after 'match'".to_string());
+    }
+    Ok(cases)
+}
+
+/// Parses the exceptions an `except` clause names, alone or in parentheses, or none for a bare `except`.
+fn parse_exceptions(tokens: &mut Vec<TokenEntry>) -> Result<Vec<String>, String> {
+    let parenthesized = matches!(peek(tokens), Token::OpenParenthesis);
+    if parenthesized {
+        eat(tokens);
+    }
+    let mut names = Vec::new();
+    while let Token::Identifier(name) = peek(tokens) {
+        names.push(name.clone());
+        eat(tokens);
+        if !parenthesized || !matches!(peek(tokens), Token::Comma) {
+            break;
+        }
+        eat(tokens);
+    }
+    if parenthesized {
+        let expected = eat(tokens);
+        if !matches!(expected.token, Token::CloseParenthesis) { return Err(format!("expected ')' but found {:?} on line {}", expected.token, expected.line_number)); }
+    }
+    if matches!(peek(tokens), Token::As) {
+        return Err(format!("'except ... as' is not supported on line {}", eat(tokens).line_number));
+    }
+    Ok(names)
+}
+
+/// Continues `first` into a tuple like `a, b` when a comma follows it, as in return values and unpacking.
+fn parse_tuple(tokens: &mut Vec<TokenEntry>, first: Expression) -> Result<Expression, String> {
+    if !matches!(peek(tokens), Token::Comma) {
+        return Ok(first);
+    }
+    let line_number = first.line_number;
+    let mut items = vec![first];
+    while matches!(peek(tokens), Token::Comma) {
+        eat(tokens);
+        if matches!(peek(tokens), Token::NewLine | Token::EOF | Token::Equals | Token::CloseParenthesis) {
+            break;
+        }
+        items.push(parse_expression(tokens, 0)?);
+    }
+    Ok(Expression::new(ExpressionType::Tuple(items), line_number))
+}
+
 fn new_line<A>(val : A, tokens: &mut Vec<TokenEntry>) -> Result<A, String> {
     let token = eat(tokens);
     if !matches!(token.token, Token::NewLine | Token::EOF) { Err(format!("expected New Line on line {}", token.line_number)) }
@@ -174,28 +405,133 @@ pub(crate) fn parse_expression(tokens : &mut Vec<TokenEntry>, min_binding : u8)
     }
     let mut first = parse_side(tokens)?;
     println!("finished first");
+    let mut comparing = false;
     loop {
         match peek(tokens) {
             Token::NewLine | Token::CloseParenthesis | Token::EOF => break,
             _ => {}
         }
+        if matches!(peek(tokens), Token::If) && min_binding == 0 {
+            first = parse_conditional(tokens, first)?;
+            break;
+        }
+        // `not in` is the only operator spelled with two tokens
+        if matches!(peek(tokens), Token::Not) && matches!(tokens.get(1), Some(TokenEntry { token: Token::In, .. })) {
+            let (precedence, _) = get_binding(&tokens[1])?;
+            if precedence < min_binding {
+                break;
+            }
+            let line_number = eat(tokens).line_number;
+            eat(tokens);
+            let second = parse_expression(tokens, precedence + 1)?;
+            let contains = Expression::new(ExpressionType::Operation(Operation::In(first, second)), line_number);
+            first = Expression::new(ExpressionType::Operation(Operation::Not(contains)), line_number);
+            comparing = false;
+            continue;
+        }
         println!("finding infix");
         if !valid_infix_operator(peek(tokens)) {
             return Ok(first);
         }
-        let operator_token = eat(tokens);
-        let operator = create_infix_operator::<()>(operator_token.clone())?;
+        let operator_token = tokens[0].clone();
         let (precedence, polarity) = get_binding(&operator_token)?;
-        if precedence < min_binding || (precedence == min_binding && matches!(polarity, Polarity::LEFT)) {
+        if precedence < min_binding {
             break;
         }
-        let second = parse_side(tokens)?;
+        let comparison = comparison_operator(&operator_token.token);
+        let operator = create_infix_operator::<()>(eat(tokens))?;
+        let next_binding = match polarity {
+            Polarity::LEFT => precedence + 1,
+            Polarity::RIGHT => precedence,
+        };
+        let second = parse_expression(tokens, next_binding)?;
 
-        first = operator(first, second);
+        first = match comparison {
+            Some(comparison) if comparing => chain_comparison(first, comparison, second),
+            _ => operator(first, second),
+        };
+        comparing = comparison.is_some();
     }
     Ok(first)
 }
 
+// a part of `[start:stop:step]`, any of which may be left out
+fn parse_slice_bound(tokens : &mut Vec<TokenEntry>) -> Result<Option<Expression>, String> {
+    if matches!(peek(tokens), Token::Colon | Token::CloseBracket) {
+        return Ok(None)
+    }
+    Ok(Some(parse_expression(tokens, 0)?))
+}
+
+// `[element for variable in iterable]`, with the opening bracket and element already parsed
+fn parse_comprehension(tokens : &mut Vec<TokenEntry>, element : Expression, line_number : usize) -> Result<Expression, String> {
+    eat(tokens);
+    let token = eat(tokens);
+    let variable = match token.token {
+        Token::Identifier(name) => name,
+        t => { return Err(format!("Expected a variable after 'for' in list comprehension, but found {:?} on line {}", t, token.line_number)) }
+    };
+    let expect = eat(tokens);
+    if !matches!(expect.token, Token::In) {
+        return Err(format!("Expected 'in' in list comprehension, but found {:?} on line {}", expect.token, expect.line_number));
+    }
+    // parsed above the conditional expression so a trailing `if` is seen as a filter
+    let iterable = parse_expression(tokens, 1)?;
+    let expect = eat(tokens);
+    match expect.token {
+        Token::CloseBracket => {}
+        Token::If | Token::For => { return Err(format!("only one 'for' and no 'if' are supported in list comprehensions on line {}", expect.line_number)) }
+        t => { return Err(format!("Expected ']' after list comprehension, but found {:?} on line {}", t, expect.line_number)) }
+    }
+    Ok(Expression::new(ExpressionType::Comprehension(element, variable, iterable), line_number))
+}
+
+// `value if condition else other`, which binds looser than every infix operator
+fn parse_conditional(tokens : &mut Vec<TokenEntry>, value : Expression) -> Result<Expression, String> {
+    let if_token = eat(tokens);
+    let condition = parse_expression(tokens, 1)?;
+    let expect = eat(tokens);
+    match expect.token {
+        Token::Else => {}
+        t => { return Err(format!("Expected 'else' in conditional expression, but found {:?} on line {}", t, expect.line_number)) }
+    }
+    let other = parse_expression(tokens, 0)?;
+    Ok(Expression::new(ExpressionType::Conditional(condition, value, other), if_token.line_number))
+}
+
+fn comparison_operator(token : &Token) -> Option<Operator> {
+    Some(match token {
+        Token::EqualsEquals => Operator::Equals,
+        Token::NotEquals => Operator::NotEqual,
+        Token::GreaterThan => Operator::GreaterThan,
+        Token::LessThan => Operator::LessThan,
+        Token::GreaterThanEquals => Operator::GreaterEquals,
+        Token::LessThanEquals => Operator::LessEquals,
+        _ => return None
+    })
+}
+
+// turns `a < b` followed by `< c` into the chain `a < b < c`, which evaluates `b` only once
+fn chain_comparison(first : Expression, operator : Operator, second : Expression) -> Expression {
+    let line_number = first.line_number;
+    let (mut operands, mut operators) = match *first.type_ {
+        ExpressionType::Comparison(operands, operators) => (operands, operators),
+        ExpressionType::Operation(operation) => match operation {
+            Operation::Equals(l, r) => (vec![l, r], vec![Operator::Equals]),
+            Operation::NotEqual(l, r) => (vec![l, r], vec![Operator::NotEqual]),
+            Operation::GreaterThan(l, r) => (vec![l, r], vec![Operator::GreaterThan]),
+            Operation::LessThan(l, r) => (vec![l, r], vec![Operator::LessThan]),
+            Operation::GreaterEquals(l, r) => (vec![l, r], vec![Operator::GreaterEquals]),
+            Operation::LessEquals(l, r) => (vec![l, r], vec![Operator::LessEquals]),
+            _ => unreachable!()
+        },
+        _ => unreachable!()
+    };
+    operands.push(second);
+    operators.push(operator);
+    Expression::new(ExpressionType::Comparison(operands, operators), line_number)
+}
+
 enum Either<T, R> {
     Left(T),
     Right(R),
@@ -203,15 +539,57 @@ enum Either<T, R> {
 
 fn parse_side(tokens : &mut Vec<TokenEntry>) -> Result<Expression, String> {
     println!("parsing side with first token: {}", &tokens[0]);
+    if matches!(peek(tokens), Token::Not) {
+        let not = eat(tokens);
+        let operand = parse_expression(tokens, NOT_BINDING)?;
+        return Ok(Expression::new(ExpressionType::Operation(Operation::Not(operand)), not.line_number));
+    }
+    if matches!(peek(tokens), Token::Lambda) {
+        let lambda = eat(tokens);
+        let mut parameters = Vec::new();
+        while let Token::Identifier(name) = peek(tokens) {
+            parameters.push(name.clone());
+            let _ = eat(tokens);
+            if !matches!(peek(tokens), Token::Comma) { break; }
+            let _ = eat(tokens);
+        }
+        let expect = eat(tokens);
+        match expect.token {
+            Token::Colon => {}
+            t => { return Err(format!("Expected ':' after lambda parameters, but found {:?} on line {}", t, expect.line_number)) }
+        }
+        let body = parse_expression(tokens, 0)?;
+        return Ok(Expression::new(ExpressionType::Lambda(parameters, body), lambda.line_number));
+    }
     let prefix = match create_prefix_operator(eat(tokens)) {
         Either::Left(v) => Some(v),
         Either::Right(r) => { tokens.insert(0, r); None }
     };
+    if let Some(prefix) = prefix {
+        return Ok(prefix(parse_expression(tokens, UNARY_BINDING)?));
+    }
     let side = eat(tokens);
     let side = match side.token {
         Token::FloatLiteral(f) => Expression::new(ExpressionType::FloatLiteral(f), side.line_number),
         Token::IntLiteral(i) => Expression::new(ExpressionType::IntLiteral(i), side.line_number),
+        Token::ImaginaryLiteral(f) => Expression::new(ExpressionType::ImaginaryLiteral(f), side.line_number),
         Token::StringLiteral(s) => Expression::new(ExpressionType::StringLiteral(s), side.line_number),
+        Token::FormatString(parts) => {
+            let mut pieces = Vec::new();
+            for part in parts {
+                pieces.push(match part {
+                    FormatPart::Text(text) => Expression::new(ExpressionType::StringLiteral(text), side.line_number),
+                    FormatPart::Expression(mut inner) => {
+                        let expression = parse_expression(&mut inner, 0)?;
+                        if let Some(extra) = inner.iter().find(|entry| entry.token != Token::EOF) {
+                            return Err(format!("Unexpected token {:?} in f-string expression on line {}", extra.token, side.line_number));
+                        }
+                        expression
+                    }
+                });
+            }
+            Expression::new(ExpressionType::FormatString(pieces), side.line_number)
+        }
         Token::BoolLiteral(b) => Expression::new(ExpressionType::BoolLiteral(b), side.line_number),
         Token::Identifier(i) => {
             if !matches!(peek(tokens), Token::OpenParenthesis) {
@@ -221,8 +599,21 @@ fn parse_side(tokens : &mut Vec<TokenEntry>) -> Result<Expression, String> {
                 Expression::new(ExpressionType::Call(i, args), side.line_number)
             }
         }
+        t @ (Token::IntType | Token::BoolType | Token::StringType | Token::Float | Token::Matrix | Token::Complex) if matches!(peek(tokens), Token::OpenParenthesis) => {
+            let name = match t {
+                Token::IntType => "int",
+                Token::BoolType => "bool",
+                Token::StringType => "str",
+                Token::Float => "float",
+                Token::Complex => "Complex",
+                _ => "Matrix",
+            };
+            let args = parse_call_args(tokens)?;
+            Expression::new(ExpressionType::Call(name.to_string(), args), side.line_number)
+        }
         Token::OpenParenthesis => {
             let value = parse_expression(tokens, 0)?;
+            let value = parse_tuple(tokens, value)?;
             let token = eat(tokens);
             match token.token {
                 Token::CloseParenthesis => value,
@@ -236,6 +627,9 @@ fn parse_side(tokens : &mut Vec<TokenEntry>) -> Result<Expression, String> {
             let mut literals = Vec::new();
             loop {
                 literals.push(parse_expression(tokens, 0)?);
+                if literals.len() == 1 && matches!(peek(tokens), Token::For) {
+                    return parse_comprehension(tokens, literals.remove(0), side.line_number);
+                }
                 let token = eat(tokens);
                 match token.token {
                     Token::CloseBracket => {
@@ -257,71 +651,125 @@ fn parse_side(tokens : &mut Vec<TokenEntry>) -> Result<Expression, String> {
         print!("{} ", token);
     }
     println!("]");
-    let side = match prefix {
-        None => side,
-        Some(entry) => {
-            entry(side)
+    let mut side = side;
+    loop {
+        if matches!(peek(tokens), Token::Dot) {
+            side = parse_member(tokens, side)?;
+            continue;
         }
-    };
-    let postfix = match create_postfix_operator(eat(tokens)) {
-        Either::Left(v) => Some(v),
-        Either::Right(r) => { tokens.insert(0, r); None }
-    };
-    if let Some(_) = postfix {
-        println!("has postfix");
-    } else {
-        println!("has no postfix");
-    }
-    Ok(if let Some(postfix) = postfix {
-        match postfix {
-            Either::Left(normal_postfix) => normal_postfix(side),
-            Either::Right(array_postfix) => {
-                let expr = parse_expression(tokens, 0)?;
+        let postfix = match create_postfix_operator(eat(tokens)) {
+            Either::Left(v) => Some(v),
+            Either::Right(r) => { tokens.insert(0, r); None }
+        };
+        if let Some(_) = postfix {
+            println!("has postfix");
+        } else {
+            println!("has no postfix");
+        }
+        side = match postfix {
+            Some(Either::Left(normal_postfix)) => normal_postfix(side),
+            Some(Either::Right(array_postfix)) => {
+                let start = parse_slice_bound(tokens)?;
+                // `m[r, c]` indexes the row, then the column
+                if let (Some(row), Token::Comma) = (&start, peek(tokens)) {
+                    let line_number = eat(tokens).line_number;
+                    side = Expression::new(ExpressionType::Operation(Operation::ArrayIndex(side, row.clone())), line_number);
+                    let column = parse_expression(tokens, 0)?;
+                    let expect = eat(tokens);
+                    match expect.token {
+                        Token::CloseBracket => {},
+                        t => { return Err(format!("expected ']', but found {:?} on line {}", t, expect.line_number)) }
+                    }
+                    side = array_postfix(column, side);
+                    continue;
+                }
+                let slice = if matches!(peek(tokens), Token::Colon) {
+                    eat(tokens);
+                    let stop = parse_slice_bound(tokens)?;
+                    let step = if matches!(peek(tokens), Token::Colon) {
+                        eat(tokens);
+                        parse_slice_bound(tokens)?
+                    } else {
+                        None
+                    };
+                    Some((stop, step))
+                } else {
+                    None
+                };
                 let expect = eat(tokens);
                 match expect.token {
                     Token::CloseBracket => {},
                     t => { return Err(format!("expected ']', but found {:?} on line {}", t, expect.line_number)) }
                 }
-                array_postfix(expr, side)
+                match (slice, start) {
+                    (Some((stop, step)), start) => {
+                        let line_number = side.line_number;
+                        Expression::new(ExpressionType::Slice(side, start, stop, step), line_number)
+                    }
+                    (None, Some(index)) => array_postfix(index, side),
+                    (None, None) => { return Err(format!("expected an index on line {}", expect.line_number)) }
+                }
             },
-        }
+            None => {
+                println!("ending side parsing");
+                break;
+            }
+        };
+    }
+    Ok(side)
+}
+
+fn parse_member(tokens : &mut Vec<TokenEntry>, receiver : Expression) -> Result<Expression, String> {
+    let dot = eat(tokens);
+    let token = eat(tokens);
+    let name = match token.token {
+        Token::Identifier(name) => name,
+        t => { return Err(format!("Expected attribute name after '.', but found {:?} on line {}", t, token.line_number)) }
+    };
+    if matches!(peek(tokens), Token::OpenParenthesis) {
+        let args = parse_call_args(tokens)?;
+        Ok(Expression::new(ExpressionType::Method(receiver, name, args), dot.line_number))
     } else {
-        println!("ending side parsing");
-        side
-    })
+        Ok(Expression::new(ExpressionType::Attribute(receiver, name), dot.line_number))
+    }
 }
 
 
 
+// Binding powers follow Python's precedence table, higher values bind tighter.
 fn get_binding(entry : &TokenEntry) -> Result<(u8, Polarity), String> {
     Ok(match entry.token {
-        Token::Plus => (6, Polarity::LEFT),
-        Token::Minus => (6, Polarity::LEFT),
-        Token::Star => (5, Polarity::LEFT),
-        Token::Slash => (5, Polarity::LEFT),
-        Token::Percent => (5, Polarity::LEFT),
-        Token::Equals => (17, Polarity::RIGHT),
-        Token::EqualsEquals => (10, Polarity::LEFT),
-        Token::NotEquals => (10, Polarity::LEFT),
-        Token::GreaterThan => (9, Polarity::LEFT),
-        Token::GreaterThanEquals => (9, Polarity::LEFT),
-        Token::LessThan => (8, Polarity::LEFT),
-        Token::LessThanEquals => (8, Polarity::LEFT),
-        Token::PlusEquals => (18, Polarity::RIGHT),
-        Token::MinusEquals => (18, Polarity::RIGHT),
-        Token::MultiplyEquals => (19, Polarity::RIGHT),
-        Token::DivideEquals => (19, Polarity::RIGHT),
-        Token::PercentEquals => (20, Polarity::RIGHT),
-        Token::LeftShift => (7, Polarity::LEFT),
-        Token::RightShift => (7, Polarity::LEFT),
-        Token::AndAnd => (14, Polarity::LEFT),
-        Token::And => (11, Polarity::LEFT),
-        Token::OrOr => (15, Polarity::LEFT),
-        Token::Or => (13, Polarity::LEFT),
+        Token::OrOr => (1, Polarity::LEFT),
+        Token::AndAnd => (2, Polarity::LEFT),
+        Token::EqualsEquals => (4, Polarity::LEFT),
+        Token::NotEquals => (4, Polarity::LEFT),
+        Token::GreaterThan => (4, Polarity::LEFT),
+        Token::GreaterThanEquals => (4, Polarity::LEFT),
+        Token::LessThan => (4, Polarity::LEFT),
+        Token::LessThanEquals => (4, Polarity::LEFT),
+        Token::In => (4, Polarity::LEFT),
+        Token::Or => (5, Polarity::LEFT),
+        Token::Caret => (6, Polarity::LEFT),
+        Token::And => (7, Polarity::LEFT),
+        Token::LeftShift => (8, Polarity::LEFT),
+        Token::RightShift => (8, Polarity::LEFT),
+        Token::Plus => (9, Polarity::LEFT),
+        Token::Minus => (9, Polarity::LEFT),
+        Token::Star => (10, Polarity::LEFT),
+        Token::Slash => (10, Polarity::LEFT),
+        Token::Percent => (10, Polarity::LEFT),
+        Token::DoubleSlash => (10, Polarity::LEFT),
+        Token::At => (10, Polarity::LEFT),
+        Token::DoubleStar => (12, Polarity::RIGHT),
         _ => return Err(format!("failed to get operator precedence of token '{:?}' on line {}", entry.token, entry.line_number))
     })
 }
 
+/// Binding power used for the operand of a prefix `not`, which binds looser than comparisons.
+const NOT_BINDING: u8 = 3;
+/// Binding power used for the operand of the other prefix operators, which bind looser than `**`.
+const UNARY_BINDING: u8 = 11;
+
 fn parse_call_args(tokens : &mut Vec<TokenEntry>) -> Result<Vec<Expression>, String> {
     let mut parameters = Vec::new();
     let token = eat(tokens);
@@ -331,7 +779,16 @@ fn parse_call_args(tokens : &mut Vec<TokenEntry>) -> Result<Vec<Expression>, Str
         return Ok(parameters);
     }
     loop {
-        parameters.push(parse_expression(tokens, 0)?);
+        if let (Some(TokenEntry { token: Token::Identifier(name), line_number }), Some(TokenEntry { token: Token::Equals, .. })) = (tokens.first(), tokens.get(1)) {
+            let (name, line_number) = (name.clone(), *line_number);
+            tokens.drain(..2);
+            let value = parse_expression(tokens, 0)?;
+            parameters.push(Expression::new(ExpressionType::Keyword(name, value), line_number));
+        } else if parameters.last().is_some_and(|last| matches!(last.type_.as_ref(), ExpressionType::Keyword(_, _))) {
+            return Err(format!("positional argument follows keyword argument on line {}", tokens.first().map_or(0, |token| token.line_number)));
+        } else {
+            parameters.push(parse_expression(tokens, 0)?);
+        }
         let token = eat(tokens);
         match token.token {
             Token::CloseParenthesis => break,
@@ -367,7 +824,10 @@ pub(crate) enum Operation {
     Sub(Expression, Expression),
     Mul(Expression, Expression),
     Div(Expression, Expression),
+    FloorDiv(Expression, Expression),
+    MatMul(Expression, Expression),
     Mod(Expression, Expression),
+    Pow(Expression, Expression),
     Equals(Expression, Expression),
     GreaterThan(Expression, Expression),
     LessThan(Expression, Expression),
@@ -378,6 +838,8 @@ pub(crate) enum Operation {
     ArrayIndex(Expression, Expression),
     BinaryAnd(Expression, Expression),
     BinaryOr(Expression, Expression),
+    BinaryXor(Expression, Expression),
+    BinaryNot(Expression),
     And(Expression, Expression),
     Or(Expression, Expression),
     Not(Expression),
@@ -385,16 +847,20 @@ pub(crate) enum Operation {
     NotEqual(Expression, Expression),
     Increment(Expression),
     Decrement(Expression),
-    Optional(Expression)
+    Optional(Expression),
+    In(Expression, Expression)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum Operator {
     Add,
     Sub,
     Mul,
     Div,
+    FloorDiv,
+    MatMul,
     Mod,
+    Pow,
     Equals,
     GreaterThan,
     LessThan,
@@ -405,6 +871,8 @@ pub(crate) enum Operator {
     ArrayIndex,
     BinaryAnd,
     BinaryOr,
+    BinaryXor,
+    BinaryNot,
     And,
     Or,
     Not,
@@ -412,7 +880,8 @@ pub(crate) enum Operator {
     NotEqual,
     Increment,
     Decrement,
-    Optional
+    Optional,
+    In
 }
 
 fn valid_infix_operator(token : &Token) -> bool {
@@ -421,10 +890,14 @@ fn valid_infix_operator(token : &Token) -> bool {
         Token::Minus |
         Token::Star |
         Token::Slash |
+        Token::DoubleSlash |
+        Token::At |
         Token::Percent |
+        Token::DoubleStar |
         Token::LeftShift |
         Token::RightShift |
         Token::EqualsEquals |
+        Token::NotEquals |
         Token::GreaterThan |
         Token::LessThan |
         Token::GreaterThanEquals |
@@ -432,12 +905,14 @@ fn valid_infix_operator(token : &Token) -> bool {
         Token::And |
         Token::AndAnd |
         Token::Or |
-        Token::OrOr
+        Token::OrOr |
+        Token::Caret |
+        Token::In
     )
 }
 
 fn valid_prefix_operator(token : &Token) -> bool {
-    matches!(token, Token::Minus | Token::Not | Token::Increment | Token::Decrement)
+    matches!(token, Token::Minus | Token::Not | Token::Tilde | Token::Increment | Token::Decrement)
 }
 
 fn valid_postfix_operator(token : &Token) -> bool {
@@ -449,6 +924,7 @@ fn create_prefix_operator(entry : TokenEntry) -> Either<impl FnOnce(Expression)
         Either::Left(move |expr| Expression::new(ExpressionType::Operation(match entry.token  {
             Token::Minus => Operation::Negative(expr),
             Token::Not => Operation::Not(expr),
+            Token::Tilde => Operation::BinaryNot(expr),
             Token::Increment => Operation::Increment(expr),
             Token::Decrement => Operation::Decrement(expr),
             _ => unreachable!()
@@ -482,14 +958,18 @@ fn create_infix_operator<T>(token: TokenEntry) -> Result<impl FnOnce(Expression,
     if valid_infix_operator(&token.token) {
         Ok(move |f, s| {
             Expression::new(ExpressionType::Operation(match token.token {
-                Token::Plus => Operation::And(f, s),
+                Token::Plus => Operation::Add(f, s),
                 Token::Minus => Operation::Sub(f, s),
                 Token::Star => Operation::Mul(f, s),
                 Token::Slash => Operation::Div(f, s),
                 Token::Percent => Operation::Mod(f, s),
+                Token::DoubleSlash => Operation::FloorDiv(f, s),
+                Token::At => Operation::MatMul(f, s),
+                Token::DoubleStar => Operation::Pow(f, s),
                 Token::LeftShift => Operation::LBS(f, s),
                 Token::RightShift => Operation::RBS(f, s),
                 Token::EqualsEquals => Operation::Equals(f, s),
+                Token::NotEquals => Operation::NotEqual(f, s),
                 Token::GreaterThan => Operation::GreaterThan(f, s),
                 Token::LessThan => Operation::LessThan(f, s),
                 Token::GreaterThanEquals => Operation::GreaterEquals(f, s),
@@ -498,6 +978,8 @@ fn create_infix_operator<T>(token: TokenEntry) -> Result<impl FnOnce(Expression,
                 Token::AndAnd => Operation::And(f, s),
                 Token::Or => Operation::BinaryOr(f, s),
                 Token::OrOr => Operation::Or(f, s),
+                Token::Caret => Operation::BinaryXor(f, s),
+                Token::In => Operation::In(f, s),
                 _ => unreachable!()
             }), token.line_number)
         })
@@ -516,25 +998,43 @@ pub enum ExpressionType {
     BoolLiteral(bool),
     IntLiteral(i64),
     FloatLiteral(f64),
+    ImaginaryLiteral(f64),
     ListLiteral(Vec<Expression>),
+    Tuple(Vec<Expression>),
+    FormatString(Vec<Expression>), // literal text and interpolated values, in order
+    Method(Expression, String, Vec<Expression>), // receiver, method name, parameters
+    Attribute(Expression, String), // receiver, attribute name
+    Comparison(Vec<Expression>, Vec<Operator>), // operands, operators between each pair
+    Conditional(Expression, Expression, Expression), // condition, value, else-value
+    Comprehension(Expression, String, Expression), // element, variable, iterable
+    Slice(Expression, Option<Expression>, Option<Expression>, Option<Expression>), // sliced value, start, stop, step
+    Keyword(String, Expression), // name=value argument of a call
+    Lambda(Vec<String>, Expression), // parameters, body
     Empty,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Statement {
     pub(crate) type_ : Box<StatementType>,
-    pub(crate) line_number : usize
+    pub(crate) line_number : usize,
+    pub(crate) directives : Vec<Directive>
 }
 
 impl Statement {
 
     pub fn new(type_ : StatementType, line_number : usize) -> Statement {
-        Statement { type_: Box::from(type_), line_number }
+        Statement { type_: Box::from(type_), line_number, directives: Vec::new() }
     }
 
 }
 
-#[derive(Debug)]
+/// A `case` of a `match`: its literal patterns, none for `_`, and its body.
+pub(crate) type Case = (Vec<Expression>, Vec<Statement>);
+
+/// An `except` clause: the exceptions it catches, none for a bare `except`, and its body.
+pub(crate) type Handler = (Vec<String>, Vec<Statement>);
+
+#[derive(Debug, Clone)]
 pub enum StatementType {
     Assignment(Expression, Expression),
     If(Expression, Vec<Statement>, Option<Statement>), // Condition, body, else-brand
@@ -542,7 +1042,16 @@ pub enum StatementType {
     For(String, String, Vec<Statement>),
     Return(Option<Expression>),
     Ignored(Expression),
-    Declaration(Expression, Type, Expression)
+    Declaration(Expression, Type, Expression),
+    Comment(String),
+    Pass,
+    Break,
+    Continue,
+    Del(Expression), // the targets, as a tuple when there are several
+    Assert(Expression, Option<Expression>), // condition, message
+    Global(Vec<String>),
+    Match(Expression, Vec<Case>),
+    Try(Vec<Statement>, Vec<Handler>, Vec<Statement>, Vec<Statement>), // body, except clauses, else, finally
 }
 
 impl Display for Operation {
@@ -553,7 +1062,10 @@ impl Display for Operation {
             Operation::Sub(_, _) | Operation::Negative(_) => "-",
             Operation::Mul(_, _) => "*",
             Operation::Div(_, _) => "/",
+            Operation::FloorDiv(_, _) => "//",
+            Operation::MatMul(_, _) => "@",
             Operation::Mod(_, _) => "%",
+            Operation::Pow(_, _) => "**",
             Operation::Equals(_, _) => "==",
             Operation::GreaterThan(_, _) => ">",
             Operation::LessThan(_, _) => "<",
@@ -562,15 +1074,18 @@ impl Display for Operation {
             Operation::ArrayIndex(_, _) => "[]",
             Operation::BinaryAnd(_, _) => "&",
             Operation::BinaryOr(_, _) => "|",
+            Operation::BinaryXor(_, _) => "^",
+            Operation::BinaryNot(_) => "~",
             Operation::And(_, _)=> "&&",
             Operation::Or(_, _) => "||",
             Operation::Not(_) => "!",
             Operation::GreaterEquals(_, _) => ">=",
-            Operation::LessEquals(_, _) => "<",
+            Operation::LessEquals(_, _) => "<=",
             Operation::NotEqual(_, _) => "!=",
             Operation::Increment(_) => "++",
             Operation::Decrement(_) => "--",
-            Operation::Optional(_) => "?"
+            Operation::Optional(_) => "?",
+            Operation::In(_, _) => "in"
         })
     }
 }
@@ -1,5 +1,5 @@
 use std::fmt::{Display};
-use crate::statements::Statement;
+use crate::statements::{Expression, Statement};
 
 #[derive(Debug)]
 pub(crate) struct Function {
@@ -7,12 +7,32 @@ pub(crate) struct Function {
     pub(crate) name: String,
     pub(crate) parameters: Vec<Parameter>,
     pub(crate) return_type: Option<Type>,
-    pub(crate) statements: Vec<Statement>
+    pub(crate) statements: Vec<Statement>,
+    pub(crate) directives: Vec<Directive>,
+    pub(crate) file: String // the source file it was declared in
 
 }
 
+/// A `@dataclass` whose fields are numbers, stored in TI lists.
 #[derive(Debug)]
+pub(crate) struct Record {
+
+    pub(crate) name: String,
+    pub(crate) fields: Vec<Parameter>
+
+}
+
+/// An instruction to the compiler written as a `# crunch: ...` comment before a statement or function.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Directive {
+    Inline,
+    NoOpt,
+    Var(String), // TI-Basic variable to hold the assigned variable
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) enum Type {
+    Bool, // held as 0 or 1, like TI-Basic's comparisons
     Int,
     String,
     Float,
@@ -20,13 +40,26 @@ pub(crate) enum Type {
     IntList,
     FloatList,
     ComplexList,
-    Matrix
+    Matrix,
+    Record(String), // one packed list holding every field
+    RecordList(String), // parallel lists, one per field
+    Tuple(Vec<Type>), // only returned and unpacked, never held in a variable
+    Callable(Vec<Type>, Option<Box<Type>>) // held as the ID of a function or lambda, called through a dispatch program
+}
+
+/// A module-level name, in ALL_CAPS or annotated `Final`, whose value is substituted wherever it is read.
+#[derive(Debug)]
+pub(crate) struct Constant {
+    pub(crate) name: String,
+    pub(crate) typetype: Option<Type>,
+    pub(crate) value: Expression,
 }
 
 #[derive(Debug)]
 pub(crate) struct  Parameter {
     pub(crate) name: String,
-    pub(crate) typetype: Type
+    pub(crate) typetype: Type,
+    pub(crate) default: Option<Expression> // a constant filled in by callers that leave the parameter out
 }
 
 #[derive(Debug, Clone)]